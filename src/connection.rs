@@ -0,0 +1,188 @@
+use crate::execution::{
+    Prepared, RowStream, ScalarFunction, Source, build_ast, create_database, execute, prepare,
+    run_pick,
+};
+use crate::parser::{Rule, parse_input};
+use crate::storage::read_database;
+use crate::structures::{
+    ActiveDataBase, AstNode, Attr, Column, DataType, Database, QueryResult, Value,
+};
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+//why a statement failed, worded the way the REPL prints it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error { message }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+//opens the connections of code that embeds RuneDB
+pub struct RuneDB;
+
+impl RuneDB {
+    //opens the data file at `path`, an empty database named after the file is made there
+    //when it does not exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Connection> {
+        let path = path.as_ref();
+        if !path.exists() {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            create_database(path, name)
+                .map_err(|e| format!("Problem creating the data file: {}", e))?;
+        }
+        let active = ActiveDataBase::open(path)
+            .map_err(|e| format!("Problem opening the data file: {}", e))?;
+        Ok(Connection {
+            active: Some(active),
+            reader: None,
+        })
+    }
+
+    //a connection without a database, `make rdb` or `open rdb` selects one
+    pub fn connect() -> Connection {
+        Connection {
+            active: None,
+            reader: None,
+        }
+    }
+}
+
+//one session on a database. connections do not share anything but the data files, each
+//has the database it opened and the functions registered on it. `make rdb` and `open rdb`
+//switch to another database with only the built-in functions, register yours again after
+pub struct Connection {
+    active: Option<ActiveDataBase>,
+    reader: Option<(File, Database)>, //the data file and catalog the rows of the last query come from
+}
+
+impl Connection {
+    //runs one statement, the rows of a pick are all read before it returns
+    pub fn execute(&mut self, statement: &str) -> Result<QueryResult> {
+        let ast = build_ast(parse_input(statement)?.into_inner().next().unwrap());
+        self.run(ast)
+    }
+
+    //runs a pick, or an explain, and gives its rows as they are read
+    pub fn query(&mut self, statement: &str) -> Result<Rows<'_>> {
+        let ast = build_ast(parse_input(statement)?.into_inner().next().unwrap());
+        self.rows(ast)
+    }
+
+    //whether a statement is a pick, or an explain, so `query` gives its rows
+    pub fn returns_rows(&self, statement: &str) -> bool {
+        parse_input(statement).is_ok_and(|parsed| {
+            parsed.into_inner().next().is_some_and(|p| {
+                matches!(
+                    p.as_rule(),
+                    Rule::pick | Rule::conditional_pick | Rule::explain
+                )
+            })
+        })
+    }
+
+    //the name of the open database
+    pub fn name(&self) -> Option<&str> {
+        self.active.as_ref().map(|a| a.active_db.name.as_str())
+    }
+
+    //parses a statement with placeholders once, see `Prepared`
+    pub fn prepare(&self, statement: &str) -> Result<Prepared> {
+        Ok(prepare(statement)?)
+    }
+
+    //a function picks and conditions of this connection can call, with fixed argument types.
+    //a value `f` gives that is not of the `result` type is null
+    pub fn register_function(
+        &mut self,
+        name: &str,
+        params: &[DataType],
+        result: DataType,
+        f: impl Fn(&[Value]) -> Value + Send + Sync + 'static,
+    ) -> Result<()> {
+        self.active()?
+            .functions
+            .register_function(name, params, result, f);
+        Ok(())
+    }
+
+    //a function that checks its own argument types
+    pub fn register(&mut self, name: &str, function: impl ScalarFunction + 'static) -> Result<()> {
+        self.active()?.functions.register(name, function);
+        Ok(())
+    }
+
+    pub(crate) fn run(&mut self, ast: AstNode) -> Result<QueryResult> {
+        Ok(execute(ast, &mut self.active)?)
+    }
+
+    pub(crate) fn rows(&mut self, ast: AstNode) -> Result<Rows<'_>> {
+        let Some(active) = &self.active else {
+            return Err(Error::from("No database is active.".to_string()));
+        };
+        let mut data_file = File::open(&active.path)
+            .map_err(|e| format!("Problem opening the data file: {}", e))?;
+        let catalog = read_database(&mut data_file)?;
+        let (data_file, db) = self.reader.insert((data_file, catalog));
+        let source = Source {
+            data_file,
+            db,
+            functions: &active.functions,
+        };
+        let (attributes, rows) = run_pick(source, ast)?;
+        Ok(Rows {
+            columns: attributes.iter().map(Column::of).collect(),
+            attributes,
+            rows,
+        })
+    }
+
+    fn active(&mut self) -> Result<&mut ActiveDataBase> {
+        self.active
+            .as_mut()
+            .ok_or_else(|| Error::from("No database is active.".to_string()))
+    }
+}
+
+//the rows of a pick, pulled from the data file a page at a time
+pub struct Rows<'c> {
+    columns: Vec<Column>,
+    attributes: Vec<Attr>,
+    rows: RowStream<'c>,
+}
+
+impl Rows<'_> {
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+}
+
+//a row that can not be read from the data file gives its error
+impl Iterator for Rows<'_> {
+    type Item = Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<Result<Vec<Value>>> {
+        self.rows.next().map(|row| match row {
+            Ok(row) => Ok(Value::row(&row, &self.attributes)),
+            Err(e) => Err(Error::from(e)),
+        })
+    }
+}
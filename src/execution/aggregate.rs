@@ -0,0 +1,210 @@
+use super::sort::compare_cells;
+use super::{key_of, parse_aggregate};
+use crate::structures::{AggregateFn, Attr, Cell, DataType, Row, Selective};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+//an aggregate of the pick list resolved against the schema, `None` is the `*` of count(*)
+#[derive(Clone, Copy)]
+pub struct AggregateColumn {
+    pub func: AggregateFn,
+    pub column: Option<(usize, DataType)>,
+}
+
+impl AggregateColumn {
+    pub fn resolve(
+        func: AggregateFn,
+        column: Option<&str>,
+        attributes: &[Attr],
+    ) -> Result<Self, String> {
+        let column = match column {
+            None if func == AggregateFn::Count => None,
+            None => return Err(format!("{:?} needs a column", func).to_lowercase()),
+            Some(name) => {
+                let index = attributes
+                    .iter()
+                    .position(|a| a.col_name == name)
+                    .ok_or_else(|| format!("Unknown column {}", name))?;
+                Some((index, attributes[index].datatype))
+            }
+        };
+        if let (AggregateFn::Sum | AggregateFn::Avg, Some((index, datatype))) = (func, column)
+            && datatype != DataType::Int
+        {
+            return Err(format!(
+                "{} of column {} needs a numeric column, it is {:?}",
+                format!("{:?}", func).to_lowercase(),
+                attributes[index].col_name,
+                datatype
+            ));
+        }
+        Ok(AggregateColumn { func, column })
+    }
+
+    pub fn accumulator(&self) -> Accumulator {
+        match self.func {
+            AggregateFn::Count => Accumulator::Count(0),
+            AggregateFn::Sum => Accumulator::Sum(None),
+            AggregateFn::Avg => Accumulator::Avg { sum: 0, count: 0 },
+            AggregateFn::Min => Accumulator::Min(None),
+            AggregateFn::Max => Accumulator::Max(None),
+        }
+    }
+}
+
+//running state of one aggregate, nulls are skipped by everything except count(*)
+#[derive(Clone)]
+pub enum Accumulator {
+    Count(i64),
+    Sum(Option<i64>),
+    Avg { sum: i64, count: i64 },
+    Min(Option<Cell>),
+    Max(Option<Cell>),
+}
+
+impl Accumulator {
+    pub fn update(&mut self, aggregate: &AggregateColumn, row: &Row) {
+        let Some((index, datatype)) = aggregate.column else {
+            if let Accumulator::Count(n) = self {
+                *n += 1;
+            }
+            return;
+        };
+        let cell = &row.cells[index];
+        if cell.null {
+            return;
+        }
+        let number = || cell.value.parse::<i64>().unwrap_or(0);
+        match self {
+            Accumulator::Count(n) => *n += 1,
+            Accumulator::Sum(sum) => *sum = Some(sum.unwrap_or(0) + number()),
+            Accumulator::Avg { sum, count } => {
+                *sum += number();
+                *count += 1;
+            }
+            Accumulator::Min(min) => {
+                if min
+                    .as_ref()
+                    .is_none_or(|m| compare_cells(cell, m, datatype) == Ordering::Less)
+                {
+                    *min = Some(cell.clone());
+                }
+            }
+            Accumulator::Max(max) => {
+                if max
+                    .as_ref()
+                    .is_none_or(|m| compare_cells(cell, m, datatype) == Ordering::Greater)
+                {
+                    *max = Some(cell.clone());
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) -> Cell {
+        match self {
+            Accumulator::Count(n) => Cell::new(n.to_string()),
+            Accumulator::Sum(sum) => sum.map_or_else(Cell::null, |s| Cell::new(s.to_string())),
+            Accumulator::Avg { count: 0, .. } => Cell::null(),
+            Accumulator::Avg { sum, count } => Cell::new((sum as f64 / count as f64).to_string()),
+            Accumulator::Min(cell) | Accumulator::Max(cell) => cell.unwrap_or_else(Cell::null),
+        }
+    }
+}
+
+//the rows of a grouped pick: one per group, holding the group by columns followed by every
+//aggregate the pick list, having or order by mention, each named the way it is written
+pub struct Grouping {
+    keys: Vec<usize>,
+    aggregates: Vec<AggregateColumn>,
+    pub attributes: Vec<Attr>,
+}
+
+impl Grouping {
+    pub fn new(group_by: &[String], attributes: &[Attr]) -> Result<Self, String> {
+        let mut grouping = Grouping {
+            keys: Vec::new(),
+            aggregates: Vec::new(),
+            attributes: Vec::new(),
+        };
+        for name in group_by {
+            let index = attributes
+                .iter()
+                .position(|a| &a.col_name == name)
+                .ok_or_else(|| format!("Unknown column {} in group by", name))?;
+            grouping.keys.push(index);
+            grouping
+                .attributes
+                .push(Attr::new(name.clone(), attributes[index].datatype));
+        }
+        Ok(grouping)
+    }
+
+    //position of a group by column or aggregate in the grouped rows, aggregates that are
+    //not computed yet are added
+    pub fn column(&mut self, name: &str, attributes: &[Attr]) -> Result<usize, String> {
+        if let Some(index) = self.attributes.iter().position(|a| a.col_name == name) {
+            return Ok(index);
+        }
+        if attributes.iter().any(|a| a.col_name == name) {
+            return Err(format!(
+                "Column {} must appear in group by to be used with aggregates",
+                name
+            ));
+        }
+        let Some(Selective::Aggregate { func, column }) = parse_aggregate(name) else {
+            return Err(format!("Unknown column {}", name));
+        };
+        let aggregate = AggregateColumn::resolve(func, column.as_deref(), attributes)?;
+        let datatype = match (func, aggregate.column) {
+            (AggregateFn::Min | AggregateFn::Max, Some((_, datatype))) => datatype,
+            _ => DataType::Int,
+        };
+        self.aggregates.push(aggregate);
+        self.attributes.push(Attr::new(name.to_string(), datatype));
+        Ok(self.attributes.len() - 1)
+    }
+
+    //folds the rows into their groups with a hash table keyed on the group by values,
+    //groups come out in the order they were first seen. without group by columns there is
+    //exactly one group, even when no row matched
+    pub fn run(&self, rows: impl Iterator<Item = Result<Row, String>>) -> Result<Vec<Row>, String> {
+        let mut positions: HashMap<Vec<Option<String>>, usize> = HashMap::new();
+        let mut groups: Vec<(Vec<Cell>, Vec<Accumulator>)> = Vec::new();
+        if self.keys.is_empty() {
+            positions.insert(Vec::new(), 0);
+            groups.push((Vec::new(), self.accumulators()));
+        }
+        for row in rows {
+            let row = row?;
+            let key: Vec<Option<String>> = self
+                .keys
+                .iter()
+                .zip(&self.attributes)
+                .map(|(&index, attr)| {
+                    let cell = &row.cells[index];
+                    (!cell.null).then(|| key_of(cell, attr.datatype))
+                })
+                .collect();
+            let position = *positions.entry(key).or_insert_with(|| {
+                let cells = self.keys.iter().map(|&i| row.cells[i].clone()).collect();
+                groups.push((cells, self.accumulators()));
+                groups.len() - 1
+            });
+            for (accumulator, aggregate) in groups[position].1.iter_mut().zip(&self.aggregates) {
+                accumulator.update(aggregate, &row);
+            }
+        }
+        Ok(groups
+            .into_iter()
+            .map(|(mut cells, accumulators)| {
+                cells.extend(accumulators.into_iter().map(|a| a.finish()));
+                Row { cells }
+            })
+            .collect())
+    }
+
+    fn accumulators(&self) -> Vec<Accumulator> {
+        self.aggregates.iter().map(|a| a.accumulator()).collect()
+    }
+}
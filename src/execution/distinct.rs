@@ -0,0 +1,53 @@
+use super::sort::{SortColumn, compare_rows, sort_rows};
+use super::{RowStream, key_of};
+use crate::structures::{DataType, Row};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+//drops repeated rows of a `distinct` pick. rows that already come in the order the pick
+//asked for, or of which only the first few are wanted, are checked against a hash set of
+//the rows seen so far and keep their order; everything else is sorted on all of its
+//columns, spilling to disk when large, so repeated rows end up next to each other
+pub fn distinct_rows<'a>(
+    rows: impl Iterator<Item = Result<Row, String>> + 'a,
+    datatypes: Vec<DataType>,
+    keep_order: bool,
+) -> Result<RowStream<'a>, String> {
+    if keep_order {
+        let mut seen = HashSet::new();
+        return Ok(Box::new(rows.filter(move |row| {
+            let Ok(row) = row else {
+                return true;
+            };
+            let key: Vec<Option<String>> = row
+                .cells
+                .iter()
+                .zip(&datatypes)
+                .map(|(cell, &datatype)| (!cell.null).then(|| key_of(cell, datatype)))
+                .collect();
+            seen.insert(key)
+        })));
+    }
+    let keys: Vec<SortColumn> = datatypes
+        .into_iter()
+        .enumerate()
+        .map(|(index, datatype)| SortColumn {
+            index,
+            datatype,
+            descending: false,
+        })
+        .collect();
+    let mut previous: Option<Row> = None;
+    Ok(Box::new(sort_rows(rows, keys.clone())?.filter(
+        move |row| {
+            let Ok(row) = row else {
+                return true;
+            };
+            let repeated = previous
+                .as_ref()
+                .is_some_and(|p| compare_rows(p, row, &keys) == Ordering::Equal);
+            previous = Some(row.clone());
+            !repeated
+        },
+    )))
+}
@@ -0,0 +1,213 @@
+use super::functions::Registry;
+use super::{decimal, operation};
+use crate::structures::{ArithOp, Attr, Cell, Condition, DataType, Expr, Row, Test, Value};
+
+//the type of an expression over the schema, arithmetic only takes numbers
+pub fn datatype(
+    expr: &Expr,
+    attributes: &[Attr],
+    functions: &Registry,
+) -> Result<DataType, String> {
+    match expr {
+        Expr::Column(name) => attributes
+            .iter()
+            .find(|a| &a.col_name == name)
+            .map(|a| a.datatype)
+            .ok_or_else(|| format!("Unknown column {}", name)),
+        Expr::Literal(value) if decimal(value).is_some() => Ok(DataType::Int),
+        Expr::Literal(value) if value == "true" || value == "false" => Ok(DataType::Bool),
+        Expr::Literal(_) => Ok(DataType::String),
+        Expr::Value(value) => Ok(value.datatype().unwrap_or(DataType::String)),
+        Expr::Binary { left, right, .. } => {
+            //a null operand makes the result null whatever the other side is
+            for side in [left, right] {
+                if let Expr::Value(Value::Null) = **side {
+                    continue;
+                }
+                let datatype = datatype(side, attributes, functions)?;
+                if datatype != DataType::Int {
+                    return Err(format!(
+                        "{} needs numbers, {} is {:?}",
+                        expr, side, datatype
+                    ));
+                }
+            }
+            Ok(DataType::Int)
+        }
+        Expr::Call { name, args } => {
+            let function = functions
+                .get(name)
+                .ok_or_else(|| format!("Unknown function {}", name))?;
+            let types = args
+                .iter()
+                .map(|a| datatype(a, attributes, functions))
+                .collect::<Result<Vec<_>, _>>()?;
+            function
+                .returns(&types)
+                .map_err(|e| format!("{}: {}", expr, e))
+        }
+        Expr::Compare { left, right, .. } => {
+            datatype(left, attributes, functions)?;
+            match &**right {
+                Expr::Column(name) if !attributes.iter().any(|a| &a.col_name == name) => {}
+                right => {
+                    datatype(right, attributes, functions)?;
+                }
+            }
+            Ok(DataType::Bool)
+        }
+        Expr::Param(_) => Err(unbound(expr)),
+    }
+}
+
+pub fn unbound(param: &Expr) -> String {
+    format!("Parameter {} has no value, bind the statement first", param)
+}
+
+//the value of an expression for one row, null when any operand is null or on division by
+//zero. whole numbers stay whole, anything with a fraction is computed as f64
+pub fn evaluate(
+    expr: &Expr,
+    row: &Row,
+    attributes: &[Attr],
+    functions: &Registry,
+) -> Result<Cell, String> {
+    match expr {
+        Expr::Column(name) => {
+            let index = attributes
+                .iter()
+                .position(|a| &a.col_name == name)
+                .ok_or_else(|| format!("Unknown column {}", name))?;
+            Ok(row.cells.get(index).cloned().unwrap_or_else(Cell::null))
+        }
+        Expr::Literal(value) => Ok(Cell::new(value.clone())),
+        Expr::Value(value) => Ok(value.cell()),
+        Expr::Param(_) => Err(unbound(expr)),
+        Expr::Binary { op, left, right } => {
+            let (l, r) = (
+                evaluate(left, row, attributes, functions)?,
+                evaluate(right, row, attributes, functions)?,
+            );
+            if l.null || r.null {
+                return Ok(Cell::null());
+            }
+            let result = match (l.value.parse::<i64>(), r.value.parse::<i64>()) {
+                (Ok(a), Ok(b)) => match op {
+                    ArithOp::Add => a.checked_add(b),
+                    ArithOp::Sub => a.checked_sub(b),
+                    ArithOp::Mul => a.checked_mul(b),
+                    ArithOp::Div => a.checked_div(b),
+                    ArithOp::Rem => a.checked_rem(b),
+                }
+                .map(|n| n.to_string()),
+                _ => match (decimal(&l.value), decimal(&r.value)) {
+                    (Some(a), Some(b)) => {
+                        let n = match op {
+                            ArithOp::Add => a + b,
+                            ArithOp::Sub => a - b,
+                            ArithOp::Mul => a * b,
+                            ArithOp::Div => a / b,
+                            ArithOp::Rem => a % b,
+                        };
+                        n.is_finite().then(|| n.to_string())
+                    }
+                    _ => None,
+                },
+            };
+            Ok(result.map_or_else(Cell::null, Cell::new))
+        }
+        Expr::Call { name, args } => {
+            let function = functions
+                .get(name)
+                .ok_or_else(|| format!("Unknown function {}", name))?;
+            let types = args
+                .iter()
+                .map(|a| datatype(a, attributes, functions))
+                .collect::<Result<Vec<_>, _>>()?;
+            let args = args
+                .iter()
+                .map(|a| evaluate(a, row, attributes, functions))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(function.call(&args, &types))
+        }
+        Expr::Compare { op, left, right } => {
+            let (l, r) = (
+                evaluate(left, row, attributes, functions)?,
+                evaluate_operand(right, row, attributes, functions)?,
+            );
+            if l.null || r.null {
+                return Ok(Cell::null());
+            }
+            let kind = datatype(left, attributes, functions)?;
+            Ok(Cell::new(operation(&l, kind, *op, r.value).to_string()))
+        }
+    }
+}
+
+//the right side of a comparison, where a lone word that names no column is a value
+pub fn evaluate_operand(
+    expr: &Expr,
+    row: &Row,
+    attributes: &[Attr],
+    functions: &Registry,
+) -> Result<Cell, String> {
+    match expr {
+        Expr::Column(name) if !attributes.iter().any(|a| &a.col_name == name) => {
+            Ok(Cell::new(name.clone()))
+        }
+        _ => evaluate(expr, row, attributes, functions),
+    }
+}
+
+//every column an expression reads, but for a lone word on the right of a comparison
+pub fn columns(expr: &Expr) -> Vec<&str> {
+    match expr {
+        Expr::Column(name) => vec![name.as_str()],
+        Expr::Literal(_) | Expr::Param(_) | Expr::Value(_) => Vec::new(),
+        Expr::Binary { left, right, .. } => {
+            let mut names = columns(left);
+            names.extend(columns(right));
+            names
+        }
+        Expr::Call { args, .. } => args.iter().flat_map(columns).collect(),
+        Expr::Compare { left, right, .. } => {
+            let mut names = columns(left);
+            if !matches!(**right, Expr::Column(_)) {
+                names.extend(columns(right));
+            }
+            names
+        }
+    }
+}
+
+//the columns a condition must find in the schema, a lone word on the right of a
+//comparison is left out as it may be a value
+pub fn condition_columns(condition: &Condition) -> Vec<&str> {
+    let mut names = condition.left.as_ref().map(columns).unwrap_or_default();
+    if let Test::Compare(_, right) = &condition.test
+        && !matches!(right, Expr::Column(_))
+    {
+        names.extend(columns(right));
+    }
+    names
+}
+
+pub fn rename(
+    expr: &mut Expr,
+    to: &mut impl FnMut(&str) -> Result<String, String>,
+) -> Result<(), String> {
+    match expr {
+        Expr::Column(name) => *name = to(name)?,
+        Expr::Literal(_) | Expr::Param(_) | Expr::Value(_) => {}
+        Expr::Binary { left, right, .. } | Expr::Compare { left, right, .. } => {
+            rename(left, to)?;
+            rename(right, to)?;
+        }
+        Expr::Call { args, .. } => {
+            for arg in args {
+                rename(arg, to)?;
+            }
+        }
+    }
+    Ok(())
+}
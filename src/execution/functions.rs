@@ -0,0 +1,550 @@
+use super::decimal;
+use crate::structures::{Cell, DataType, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//a function expressions can call. `returns` checks the argument types when the pick is
+//planned and gives the type of the result, `call` computes the result for one row from
+//arguments read as those types. a result that is not of the type `returns` gave is null
+pub trait ScalarFunction: Send + Sync {
+    fn returns(&self, args: &[DataType]) -> Result<DataType, String>;
+    fn call(&self, args: &[Value]) -> Value;
+}
+
+//what the registry calls: the built-ins work on the stored cells, a registered function
+//gets its arguments as values of the types they were planned with
+pub trait Function: Send + Sync {
+    fn returns(&self, args: &[DataType]) -> Result<DataType, String>;
+    fn call(&self, args: &[Cell], types: &[DataType]) -> Cell;
+}
+
+struct Registered<F>(F);
+
+impl<F: ScalarFunction> Function for Registered<F> {
+    fn returns(&self, args: &[DataType]) -> Result<DataType, String> {
+        self.0.returns(args)
+    }
+
+    fn call(&self, args: &[Cell], types: &[DataType]) -> Cell {
+        let values: Vec<Value> = args
+            .iter()
+            .zip(types)
+            .map(|(cell, &datatype)| Value::of(cell, datatype))
+            .collect();
+        let value = self.0.call(&values);
+        match self.0.returns(types) {
+            Ok(datatype) if value.fits(datatype) => value.cell(),
+            _ => Cell::null(),
+        }
+    }
+}
+
+//a function given as a closure, its arguments must have exactly the types of `params`
+struct Closure<F> {
+    params: Vec<DataType>,
+    result: DataType,
+    f: F,
+}
+
+impl<F: Fn(&[Value]) -> Value + Send + Sync> ScalarFunction for Closure<F> {
+    fn returns(&self, args: &[DataType]) -> Result<DataType, String> {
+        fixed_params(&self.params, self.result, args)
+    }
+
+    fn call(&self, args: &[Value]) -> Value {
+        (self.f)(args)
+    }
+}
+
+fn fixed_params(
+    params: &[DataType],
+    result: DataType,
+    args: &[DataType],
+) -> Result<DataType, String> {
+    if args.len() != params.len() {
+        return Err(format!(
+            "takes {} arguments, got {}",
+            params.len(),
+            args.len()
+        ));
+    }
+    match (0..args.len()).find(|&i| args[i] != params[i]) {
+        Some(i) => Err(format!(
+            "argument {} must be {:?}, it is {:?}",
+            i + 1,
+            params[i],
+            args[i]
+        )),
+        None => Ok(result),
+    }
+}
+
+//checks the argument types of a call and gives the type of its result
+type Returns = Box<dyn Fn(&[DataType]) -> Result<DataType, String> + Send + Sync>;
+
+//a built-in taking any number of arguments between `min_args` and `max_args`, `returns`
+//checks their types
+struct Builtin {
+    min_args: usize,
+    max_args: Option<usize>, //None for any number
+    returns: Returns,
+    call: fn(&[Cell]) -> Cell,
+}
+
+impl Function for Builtin {
+    fn returns(&self, args: &[DataType]) -> Result<DataType, String> {
+        if args.len() < self.min_args || self.max_args.is_some_and(|m| args.len() > m) {
+            return Err(match self.max_args {
+                Some(max) if max == self.min_args => format!("takes {} arguments", max),
+                Some(max) => format!("takes {} to {} arguments", self.min_args, max),
+                None => format!("takes at least {} arguments", self.min_args),
+            });
+        }
+        (self.returns)(args)
+    }
+
+    fn call(&self, args: &[Cell], _: &[DataType]) -> Cell {
+        (self.call)(args)
+    }
+}
+
+//the functions of one database by name: the built-ins and those registered on it
+#[derive(Clone)]
+pub struct Registry {
+    functions: HashMap<String, Arc<dyn Function>>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.functions.keys().collect();
+        names.sort();
+        f.debug_set().entries(names).finish()
+    }
+}
+
+impl Default for Registry {
+    //the functions RuneDB ships with, a new one only needs a line here
+    fn default() -> Self {
+        let mut registry = Registry {
+            functions: HashMap::new(),
+        };
+        let string = [DataType::String];
+        //strings
+        registry.fixed("upper", &string, DataType::String, upper);
+        registry.fixed("lower", &string, DataType::String, lower);
+        registry.fixed("trim", &string, DataType::String, trim);
+        registry.fixed("length", &string, DataType::Int, length);
+        registry.builtin("substr", 2, Some(3), substr_type, substr);
+        registry.builtin("concat", 1, None, |_| Ok(DataType::String), concat);
+        //numbers
+        registry.fixed("abs", &[DataType::Int], DataType::Int, abs);
+        registry.builtin("round", 1, Some(2), numbers, round);
+        registry.fixed("mod", &[DataType::Int; 2], DataType::Int, modulo);
+        //conditionals
+        registry.builtin("coalesce", 1, None, same_type, coalesce);
+        registry.builtin("if", 3, Some(3), case_type, case);
+        registry.builtin("case", 2, None, case_type, case);
+        //dates, written as yyyy-mm-dd strings
+        registry.fixed("today", &[], DataType::String, today);
+        registry.fixed("year", &string, DataType::Int, year);
+        registry.fixed("month", &string, DataType::Int, month);
+        registry.fixed("day", &string, DataType::Int, day);
+        let date_and_days = [DataType::String, DataType::Int];
+        registry.fixed("date_add", &date_and_days, DataType::String, date_add);
+        let dates = [DataType::String; 2];
+        registry.fixed("days_between", &dates, DataType::Int, days_between);
+        registry
+    }
+}
+
+impl Registry {
+    pub fn get(&self, name: &str) -> Option<&dyn Function> {
+        self.functions.get(name).map(|f| &**f)
+    }
+
+    //names are not case sensitive, a function registered again replaces the earlier one
+    pub fn register(&mut self, name: &str, function: impl ScalarFunction + 'static) {
+        self.functions
+            .insert(name.to_lowercase(), Arc::new(Registered(function)));
+    }
+
+    //registers a closure over values of the given types
+    pub fn register_function(
+        &mut self,
+        name: &str,
+        params: &[DataType],
+        result: DataType,
+        f: impl Fn(&[Value]) -> Value + Send + Sync + 'static,
+    ) {
+        let params = params.to_vec();
+        self.register(name, Closure { params, result, f });
+    }
+
+    //a built-in with arguments of exactly the types of `params`
+    fn fixed(
+        &mut self,
+        name: &str,
+        params: &[DataType],
+        result: DataType,
+        call: fn(&[Cell]) -> Cell,
+    ) {
+        let params = params.to_vec();
+        let count = params.len();
+        let builtin = Builtin {
+            min_args: count,
+            max_args: Some(count),
+            returns: Box::new(move |args| fixed_params(&params, result, args)),
+            call,
+        };
+        self.functions.insert(name.to_string(), Arc::new(builtin));
+    }
+
+    fn builtin(
+        &mut self,
+        name: &str,
+        min_args: usize,
+        max_args: Option<usize>,
+        returns: fn(&[DataType]) -> Result<DataType, String>,
+        call: fn(&[Cell]) -> Cell,
+    ) {
+        let builtin = Builtin {
+            min_args,
+            max_args,
+            returns: Box::new(returns),
+            call,
+        };
+        self.functions.insert(name.to_string(), Arc::new(builtin));
+    }
+}
+
+fn numbers(args: &[DataType]) -> Result<DataType, String> {
+    expect(args, DataType::Int).map(|_| DataType::Int)
+}
+
+fn expect(args: &[DataType], datatype: DataType) -> Result<(), String> {
+    match args.iter().position(|&a| a != datatype) {
+        Some(i) => Err(format!(
+            "argument {} must be {:?}, it is {:?}",
+            i + 1,
+            datatype,
+            args[i]
+        )),
+        None => Ok(()),
+    }
+}
+
+fn substr_type(args: &[DataType]) -> Result<DataType, String> {
+    expect(&args[..1], DataType::String)?;
+    expect(&args[1..], DataType::Int).map_err(|_| "positions must be Int".to_string())?;
+    Ok(DataType::String)
+}
+
+fn same_type(args: &[DataType]) -> Result<DataType, String> {
+    match args.iter().find(|&&a| a != args[0]) {
+        Some(other) => Err(format!(
+            "arguments must share one type, found {:?} and {:?}",
+            args[0], other
+        )),
+        None => Ok(args[0]),
+    }
+}
+
+//`if(test, then, else)` and `case(test, value, test, value, ..., else)`
+fn case_type(args: &[DataType]) -> Result<DataType, String> {
+    let mut tests = args.chunks(2).filter(|pair| pair.len() == 2);
+    if tests.any(|pair| pair[0] != DataType::Bool) {
+        return Err("every test must be a comparison".to_string());
+    }
+    let values: Vec<DataType> = args.chunks(2).map(|pair| pair[pair.len() - 1]).collect();
+    same_type(&values)
+}
+
+fn text(args: &[Cell], f: impl Fn(&str) -> String) -> Cell {
+    match args.first() {
+        Some(cell) if !cell.null => Cell::new(f(&cell.value)),
+        _ => Cell::null(),
+    }
+}
+
+fn integer(cell: &Cell) -> Option<i64> {
+    (!cell.null).then(|| cell.value.parse().ok()).flatten()
+}
+
+fn number(cell: &Cell) -> Option<f64> {
+    (!cell.null).then(|| decimal(&cell.value)).flatten()
+}
+
+fn upper(args: &[Cell]) -> Cell {
+    text(args, str::to_uppercase)
+}
+
+fn lower(args: &[Cell]) -> Cell {
+    text(args, str::to_lowercase)
+}
+
+fn trim(args: &[Cell]) -> Cell {
+    text(args, |s| s.trim().to_string())
+}
+
+fn length(args: &[Cell]) -> Cell {
+    text(args, |s| s.chars().count().to_string())
+}
+
+//`substr(text, start, length)` counts characters from 1
+fn substr(args: &[Cell]) -> Cell {
+    let Some(start) = integer(&args[1]) else {
+        return Cell::null();
+    };
+    let length = match args.get(2) {
+        Some(cell) => match integer(cell) {
+            Some(length) => length.max(0) as usize,
+            None => return Cell::null(),
+        },
+        None => usize::MAX,
+    };
+    text(args, |s| {
+        s.chars()
+            .skip(start.max(1) as usize - 1)
+            .take(length)
+            .collect()
+    })
+}
+
+//nulls are left out
+fn concat(args: &[Cell]) -> Cell {
+    Cell::new(
+        args.iter()
+            .filter(|c| !c.null)
+            .map(|c| c.value.as_str())
+            .collect(),
+    )
+}
+
+fn abs(args: &[Cell]) -> Cell {
+    match (integer(&args[0]), number(&args[0])) {
+        (Some(n), _) => n
+            .checked_abs()
+            .map_or_else(Cell::null, |n| Cell::new(n.to_string())),
+        (None, Some(n)) => Cell::new(n.abs().to_string()),
+        _ => Cell::null(),
+    }
+}
+
+//`round(number, digits)`, to a whole number without digits
+fn round(args: &[Cell]) -> Cell {
+    let digits = match args.get(1) {
+        Some(cell) => match integer(cell) {
+            Some(digits) => digits.clamp(0, 15) as i32,
+            None => return Cell::null(),
+        },
+        None => 0,
+    };
+    match number(&args[0]) {
+        Some(n) => {
+            let scale = 10f64.powi(digits);
+            Cell::new(((n * scale).round() / scale).to_string())
+        }
+        None => Cell::null(),
+    }
+}
+
+fn modulo(args: &[Cell]) -> Cell {
+    match (integer(&args[0]), integer(&args[1])) {
+        (Some(a), Some(b)) => a
+            .checked_rem(b)
+            .map_or_else(Cell::null, |n| Cell::new(n.to_string())),
+        _ => match (number(&args[0]), number(&args[1])) {
+            (Some(a), Some(b)) if b != 0.0 => Cell::new((a % b).to_string()),
+            _ => Cell::null(),
+        },
+    }
+}
+
+fn coalesce(args: &[Cell]) -> Cell {
+    args.iter()
+        .find(|c| !c.null)
+        .cloned()
+        .unwrap_or_else(Cell::null)
+}
+
+//the value after the first test that holds, else the last argument when it has no test
+fn case(args: &[Cell]) -> Cell {
+    for pair in args.chunks(2) {
+        match pair {
+            [test, value] if !test.null && test.value == "true" => return value.clone(),
+            [value] => return value.clone(),
+            _ => {}
+        }
+    }
+    Cell::null()
+}
+
+fn today(_: &[Cell]) -> Cell {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86400);
+    Cell::new(format_date(days as i64))
+}
+
+fn year(args: &[Cell]) -> Cell {
+    date_part(args, |(y, _, _)| y)
+}
+
+fn month(args: &[Cell]) -> Cell {
+    date_part(args, |(_, m, _)| m)
+}
+
+fn day(args: &[Cell]) -> Cell {
+    date_part(args, |(_, _, d)| d)
+}
+
+fn date_part(args: &[Cell], part: fn((i64, i64, i64)) -> i64) -> Cell {
+    match parse_date(&args[0]) {
+        Some(days) => Cell::new(part(civil_from_days(days)).to_string()),
+        None => Cell::null(),
+    }
+}
+
+fn date_add(args: &[Cell]) -> Cell {
+    match (parse_date(&args[0]), integer(&args[1])) {
+        (Some(days), Some(n)) => days
+            .checked_add(n)
+            .and_then(in_range)
+            .map_or_else(Cell::null, |days| Cell::new(format_date(days))),
+        _ => Cell::null(),
+    }
+}
+
+fn days_between(args: &[Cell]) -> Cell {
+    match (parse_date(&args[0]), parse_date(&args[1])) {
+        (Some(from), Some(to)) => Cell::new((to - from).to_string()),
+        _ => Cell::null(),
+    }
+}
+
+//days since 1970-01-01 of a yyyy-mm-dd date, None when it is not a real date or its year
+//has more than four digits
+fn parse_date(cell: &Cell) -> Option<i64> {
+    if cell.null {
+        return None;
+    }
+    let mut parts = cell.value.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (y, m, d) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(0..=9999).contains(&y) || !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        return None;
+    }
+    let days = days_from_civil(y, m, d);
+    (civil_from_days(days) == (y, m, d)).then_some(days)
+}
+
+//a day count that is still a date of 0000-01-01 to 9999-12-31
+fn in_range(days: i64) -> Option<i64> {
+    (days_from_civil(0, 1, 1)..=days_from_civil(9999, 12, 31))
+        .contains(&days)
+        .then_some(days)
+}
+
+fn format_date(days: i64) -> String {
+    let (y, m, d) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+//proleptic gregorian calendar, counted in 400 year eras of 146097 days
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (
+        if m <= 2 {
+            yoe + era * 400 + 1
+        } else {
+            yoe + era * 400
+        },
+        m,
+        d,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> Cell {
+        Cell::new(value.to_string())
+    }
+
+    #[test]
+    fn day_counts_round_trip_through_the_calendar() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for days in [-719468, -1, 0, 59, 11016, 2932896] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+
+    #[test]
+    fn only_real_four_digit_dates_parse() {
+        assert_eq!(parse_date(&date("2024-02-29")), Some(19782));
+        assert_eq!(parse_date(&date("2023-02-29")), None);
+        assert_eq!(parse_date(&date("2023-13-01")), None);
+        assert_eq!(parse_date(&date("9223372036854775807-01-01")), None);
+        assert_eq!(parse_date(&Cell::null()), None);
+    }
+
+    #[test]
+    fn date_arithmetic_gives_null_out_of_range() {
+        let add = |d: &str, n: &str| date_add(&[date(d), date(n)]);
+        assert_eq!(add("2024-02-28", "1").value, "2024-02-29");
+        assert_eq!(add("2024-01-31", "-31").value, "2023-12-31");
+        assert!(add("2024-02-28", "9223372036854775807").null);
+        assert!(add("0000-01-01", "-1").null);
+        assert!(year(&[date("9223372036854775807-01-01")]).null);
+        let between = days_between(&[date("2024-01-01"), date("2025-01-01")]);
+        assert_eq!(between.value, "366");
+    }
+
+    #[test]
+    fn registered_functions_get_typed_values() {
+        let mut registry = Registry::default();
+        registry.register_function("kind", &[DataType::Int], DataType::String, |args| {
+            Value::Text(format!("{:?}", args[0]))
+        });
+        let kind = registry.get("kind").unwrap();
+        let call = |cell: Cell| kind.call(&[cell], &[DataType::Int]).value;
+        assert_eq!(call(Cell::new("7".to_string())), "Int(7)");
+        assert_eq!(call(Cell::null()), "Null");
+        assert!(kind.returns(&[DataType::String]).is_err());
+    }
+
+    #[test]
+    fn results_of_another_type_are_null() {
+        let mut registry = Registry::default();
+        registry.register_function("half", &[DataType::Int], DataType::Int, |args| {
+            match args[0] {
+                Value::Int(n) if n % 2 == 0 => Value::Int(n / 2),
+                _ => Value::Text("odd".to_string()),
+            }
+        });
+        let half = registry.get("half").unwrap();
+        let call = |n: &str| half.call(&[Cell::new(n.to_string())], &[DataType::Int]);
+        assert_eq!(call("8").value, "4");
+        assert!(call("7").null);
+    }
+}
@@ -0,0 +1,278 @@
+use super::expr::rename;
+use super::{RowStream, decimal, operation, parse_aggregate};
+use crate::structures::{
+    Attr, Cell, Clauses, Condition, DataType, Join, JoinCondition, Operation, Row, Selective,
+    TableSchema, Test,
+};
+use std::collections::HashMap;
+
+//the columns a pick reads: those of the picked table followed by those of every joined
+//table. a column keeps its plain name unless another of the tables has a column of the
+//same name, then it is only known by its `table.column` name
+pub struct Schema {
+    pub attributes: Vec<Attr>,
+    origins: Vec<(String, String)>,
+}
+
+impl Schema {
+    pub fn new(tables: &[&TableSchema]) -> Self {
+        let mut schema = Schema {
+            attributes: Vec::new(),
+            origins: Vec::new(),
+        };
+        for table in tables {
+            for attr in &table.attributes {
+                let shared = tables.iter().filter(|t| t.name != table.name).any(|t| {
+                    t.attributes
+                        .iter()
+                        .any(|other| other.col_name == attr.col_name)
+                });
+                let name = if shared {
+                    format!("{}.{}", table.name, attr.col_name)
+                } else {
+                    attr.col_name.clone()
+                };
+                schema.attributes.push(Attr::new(name, attr.datatype));
+                schema
+                    .origins
+                    .push((table.name.clone(), attr.col_name.clone()));
+            }
+        }
+        schema
+    }
+
+    //the name the schema shows for a column or an aggregate over one, names the schema
+    //does not know are kept for the caller to report
+    pub fn resolve(&self, name: &str) -> Result<String, String> {
+        if let Some(Selective::Aggregate {
+            func,
+            column: Some(column),
+        }) = parse_aggregate(name)
+        {
+            let column = Some(self.resolve(&column)?);
+            return Ok(Selective::Aggregate { func, column }.to_string());
+        }
+        let found: Vec<usize> = match name.split_once('.') {
+            Some((table, column)) => self
+                .origins
+                .iter()
+                .position(|(t, c)| t == table && c == column)
+                .into_iter()
+                .collect(),
+            None => (0..self.origins.len())
+                .filter(|&i| self.origins[i].1 == name)
+                .collect(),
+        };
+        match found[..] {
+            [] => Ok(name.to_string()),
+            [index] => Ok(self.attributes[index].col_name.clone()),
+            _ => Err(format!(
+                "Column {} is ambiguous, qualify it with its table",
+                name
+            )),
+        }
+    }
+
+    //rewrites every name of the pick to the one the schema shows
+    pub fn qualify(
+        &self,
+        columns: &mut [Selective],
+        conditions: &mut [Condition],
+        clauses: &mut Clauses,
+    ) -> Result<(), String> {
+        let mut resolve = |name: &str| self.resolve(name);
+        for column in columns {
+            match column {
+                Selective::Column(name) => *name = resolve(name)?,
+                Selective::Aggregate {
+                    column: Some(name), ..
+                } => *name = resolve(name)?,
+                Selective::Aggregate { column: None, .. } => {}
+                Selective::Expr { expr, .. } => rename(expr, &mut resolve)?,
+            }
+        }
+        for condition in conditions.iter_mut().chain(clauses.having.iter_mut()) {
+            if let Some(left) = &mut condition.left {
+                rename(left, &mut resolve)?;
+            }
+            if let Test::Compare(_, right) = &mut condition.test {
+                rename(right, &mut resolve)?;
+            }
+        }
+        for name in &mut clauses.group_by {
+            *name = self.resolve(name)?;
+        }
+        for key in &mut clauses.order_by {
+            key.column = self.resolve(&key.column)?;
+        }
+        Ok(())
+    }
+
+    fn position(&self, name: &str) -> Result<usize, String> {
+        let name = self.resolve(name)?;
+        self.attributes
+            .iter()
+            .position(|a| a.col_name == name)
+            .ok_or_else(|| format!("Unknown column {} in join", name))
+    }
+}
+
+//one test of a join, `left` indexes the rows joined so far and `right` the joined table
+struct JoinTest {
+    left: usize,
+    op: Operation,
+    right: usize,
+    datatype: DataType, //of the left column, the order both sides compare in
+}
+
+pub struct JoinPlan {
+    pub left: bool,
+    width: usize,
+    tests: Vec<JoinTest>,
+}
+
+impl JoinPlan {
+    //`start` is where the columns of the joined table begin in the schema, `width` how many
+    //there are
+    pub fn new(join: &Join, schema: &Schema, start: usize, width: usize) -> Result<Self, String> {
+        let mut tests = Vec::new();
+        for JoinCondition { left, op, right } in &join.on {
+            let (l, r) = (schema.position(left)?, schema.position(right)?);
+            let joined = start..start + width;
+            let test = if l < start && joined.contains(&r) {
+                JoinTest {
+                    left: l,
+                    op: *op,
+                    right: r - start,
+                    datatype: schema.attributes[l].datatype,
+                }
+            } else if r < start
+                && joined.contains(&l)
+                && let Some(op) = flip(*op)
+            {
+                JoinTest {
+                    left: r,
+                    op,
+                    right: l - start,
+                    datatype: schema.attributes[r].datatype,
+                }
+            } else {
+                return Err(format!(
+                    "Join condition on {} and {} must compare a column of an earlier table with one of {}",
+                    left, right, join.table
+                ));
+            };
+            tests.push(test);
+        }
+        Ok(JoinPlan {
+            left: join.left,
+            width,
+            tests,
+        })
+    }
+
+    //an equality test lets the join look rows up in a hash table
+    pub fn hashed(&self) -> bool {
+        self.tests.iter().any(|t| matches!(t.op, Operation::Equal))
+    }
+
+    //joins every incoming row with the matching rows of the joined table. with an equality
+    //test the joined rows are put in a hash table on its columns and each incoming row only
+    //looks at its own bucket, otherwise it is compared with every joined row
+    pub fn run<'a>(self, rows: RowStream<'a>, joined: Vec<Row>) -> RowStream<'a> {
+        let equalities: Vec<usize> = (0..self.tests.len())
+            .filter(|&i| matches!(self.tests[i].op, Operation::Equal))
+            .collect();
+        if equalities.is_empty() {
+            return Box::new(rows.flat_map(move |row| {
+                let row = match row {
+                    Ok(row) => row,
+                    Err(e) => return vec![Err(e)],
+                };
+                let found = joined.iter().filter(|j| self.matches(&row, j));
+                self.combine(&row, found)
+            }));
+        }
+
+        let mut buckets: HashMap<Vec<String>, Vec<Row>> = HashMap::new();
+        for row in joined {
+            let key: Option<Vec<String>> = equalities
+                .iter()
+                .map(|&i| join_key(&row.cells[self.tests[i].right]))
+                .collect();
+            //a null never equals anything, so such rows can not be matched
+            if let Some(key) = key {
+                buckets.entry(key).or_default().push(row);
+            }
+        }
+        Box::new(rows.flat_map(move |row| {
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => return vec![Err(e)],
+            };
+            let key: Option<Vec<String>> = equalities
+                .iter()
+                .map(|&i| join_key(&row.cells[self.tests[i].left]))
+                .collect();
+            let bucket = key.and_then(|key| buckets.get(&key));
+            let found = bucket
+                .into_iter()
+                .flatten()
+                .filter(|j| self.matches(&row, j));
+            self.combine(&row, found)
+        }))
+    }
+
+    fn matches(&self, row: &Row, joined: &Row) -> bool {
+        self.tests.iter().all(|test| {
+            let value = &joined.cells[test.right];
+            !value.null
+                && operation(
+                    &row.cells[test.left],
+                    test.datatype,
+                    test.op,
+                    value.value.clone(),
+                )
+        })
+    }
+
+    fn combine<'r>(
+        &self,
+        row: &Row,
+        found: impl Iterator<Item = &'r Row>,
+    ) -> Vec<Result<Row, String>> {
+        let mut combined: Vec<Result<Row, String>> = found
+            .map(|joined| {
+                Ok(Row {
+                    cells: row.cells.iter().chain(&joined.cells).cloned().collect(),
+                })
+            })
+            .collect();
+        if combined.is_empty() && self.left {
+            let mut cells = row.cells.clone();
+            cells.resize_with(row.cells.len() + self.width, Cell::null);
+            combined.push(Ok(Row { cells }));
+        }
+        combined
+    }
+}
+
+//`a op b` written the other way around, patterns only read one way
+fn flip(op: Operation) -> Option<Operation> {
+    match op {
+        Operation::Equal | Operation::NotEq => Some(op),
+        Operation::Grt => Some(Operation::Les),
+        Operation::Les => Some(Operation::Grt),
+        Operation::GrtEq => Some(Operation::LesEq),
+        Operation::LesEq => Some(Operation::GrtEq),
+        _ => None,
+    }
+}
+
+//values that compare equal share a key: numbers are keyed by their value, so 7 meets 07
+fn join_key(cell: &Cell) -> Option<String> {
+    if cell.null {
+        return None;
+    }
+    Some(decimal(&cell.value).map_or_else(|| cell.value.clone(), |n| n.to_string()))
+}
@@ -1,494 +1,1940 @@
-use crate::parser::Rule;
+mod aggregate;
+mod distinct;
+mod expr;
+mod functions;
+mod join;
+mod pattern;
+mod plan;
+mod prepared;
+mod remove;
+mod sort;
+mod stats;
+
+use crate::parser::{MyParser, Rule};
+use crate::storage::{
+    KeyIndex, TableScan, add_keys, append_rows, check_database, create_table, fits_page, move_keys,
+    read_database, read_key_index, read_rows_page, read_stats, read_table_info, scan_table,
+    write_database, write_rows_page, write_stats, write_table_info,
+};
 use crate::structures::{
-    ActiveDataBase, AstNode, Attr, Cell, DataType, Database, Operation, Row, TableInfo, TableRow,
-    TableSchema,
+    ActiveDataBase, AggregateFn, ArithOp, Assignment, AstNode, Attr, Cell, Clauses, ColumnStats,
+    Condition, Conflict, DataType, Database, Expr, ForeignKey, Join, JoinCondition, KeyEntry,
+    OnDelete, Operation, QueryResult, Row, Selective, SortKey, TableInfo, TableRow, TableSchema,
+    Test, Value, ViewSchema,
+};
+use aggregate::Grouping;
+use distinct::distinct_rows;
+use expr::{
+    columns as expr_columns, condition_columns, datatype, evaluate, evaluate_operand, unbound,
 };
-use std::collections::HashMap;
+pub use functions::{Registry, ScalarFunction};
+use join::{JoinPlan, Schema};
+use pattern::{check_pattern, pattern_match};
+use pest::Parser;
+use plan::{Keys, Plan, key_values};
+pub use prepared::{Prepared, prepare};
+use remove::remove;
+use sort::{SortColumn, compare_cells};
+use stats::collect_stats;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::io::{Seek, SeekFrom};
-const PAGE_SIZE: usize = 4096;
-use lazy_static::lazy_static;
-use std::sync::Mutex;
-use std::fs;
-
-lazy_static! {
-    pub static ref ACTIVE_DB: Mutex<Option<ActiveDataBase>> = Mutex::new(None);
-}
+use std::path::Path;
 
 fn db_initialise(name: String) -> Database {
-    let db = Database {
+    Database {
         tables: 0,
-        name: name,
+        name,
         table_details: Vec::new(),
-    };
-    return db;
+        views: Vec::new(),
+    }
 }
 
 fn table_initialise() -> TableInfo {
-    let table_info = TableInfo {
+    TableInfo {
         tables: HashMap::new(),
-    };
-    return table_info;
+        tails: HashMap::new(),
+        indexes: HashMap::new(),
+        stats: HashMap::new(),
+    }
 }
 
+//writes the empty catalog and table directory of a new data file
+pub fn create_database(path: &Path, name: String) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut data_file = File::create(path)?;
 
-fn check_validity(row: &Row, attributes: &Vec<Attr>) -> bool {
-    if row.cells.len() != attributes.len() {
-        println!(
-            "Column count mismatch: expected {}, got {}",
-            attributes.len(),
-            row.cells.len()
-        );
-        return false;
-    } else {
-        for (cell, attr) in row.cells.iter().zip(attributes.iter()) {
-            let value = &cell.value;
-
-            match attr.datatype {
-                DataType::Int => {
-                    if value.parse::<i32>().is_err() {
-                        println!("Column {} expects Int, got {}", attr.col_name, value);
-                        return false;
-                    }
-                }
-                DataType::Bool => {
-                    if value.parse::<bool>().is_err() {
-                        println!("Column {} expects a boolean, got {}", attr.col_name, value);
-                        return false;
-                    }
-                }
-                DataType::String => {
-                    return true;
-                }
+    let db = db_initialise(name);
+    let db_buf = rmp_serde::to_vec(&db).unwrap();
+    data_file.write_all(&db_buf)?;
+
+    data_file.seek(SeekFrom::Start(4096))?;
+    let tbl = table_initialise();
+    let tbl_buf = rmp_serde::to_vec(&tbl).unwrap();
+    data_file.write_all(&tbl_buf)
+}
+
+fn open_data_file(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("Problem opening the data file: {}", e))
+}
+
+fn active_database(active: &Option<ActiveDataBase>) -> Result<&ActiveDataBase, String> {
+    active
+        .as_ref()
+        .ok_or_else(|| "No database is active.".to_string())
+}
+
+fn check_value(value: &str, attr: &Attr) -> Result<(), String> {
+    match attr.datatype {
+        DataType::Int => {
+            if value.parse::<i32>().is_err() {
+                return Err(format!(
+                    "Column {} expects Int, got {}",
+                    attr.col_name, value
+                ));
             }
         }
-        true
+        DataType::Bool => {
+            if value.parse::<bool>().is_err() {
+                return Err(format!(
+                    "Column {} expects a boolean, got {}",
+                    attr.col_name, value
+                ));
+            }
+        }
+        DataType::String => {}
     }
+    Ok(())
 }
 
-fn operation(cell: &Cell, op: Operation, val: String) -> bool {
-    let cell_num = cell.value.parse::<i32>();
-    let val_num = val.parse::<i32>();
+//positional values fill the columns in order; when fewer values than columns are given,
+//autoincrement columns are generated instead of taking a value and the rest use their default.
+//named values may come in any order, columns left out are generated, defaulted or null
+fn build_row(
+    values: Vec<Assignment>,
+    schema: &TableSchema,
+    mut last_id: i32,
+) -> Result<(Row, i32), String> {
+    let named = values.iter().filter(|a| a.column.is_some()).count();
+    let mut row = Row { cells: Vec::new() };
+
+    if named == 0 {
+        let generate = values.len() < schema.attributes.len();
+        let mut values = values.into_iter().map(|a| a.value);
+        for attr in &schema.attributes {
+            let cell = if attr.autoincrement && generate {
+                last_id += 1;
+                Some(Cell::new(last_id.to_string()))
+            } else {
+                match values.next() {
+                    Some(value) => Some(literal_cell(value, Some(attr))?),
+                    None => attr.default.clone().map(Cell::new),
+                }
+            };
+            match cell {
+                Some(cell) => row.cells.push(cell),
+                None => break,
+            }
+        }
+        for value in values {
+            row.cells.push(literal_cell(value, None)?);
+        }
+    } else if named == values.len() {
+        let mut by_name: HashMap<String, Expr> = HashMap::new();
+        for assignment in values {
+            let column = assignment.column.unwrap();
+            if !schema.attributes.iter().any(|a| a.col_name == column) {
+                return Err(format!(
+                    "Unknown column {} in table {}",
+                    column, schema.name
+                ));
+            }
+            if by_name.insert(column.clone(), assignment.value).is_some() {
+                return Err(format!("Column {} assigned more than once", column));
+            }
+        }
+        for attr in &schema.attributes {
+            let cell = match by_name.remove(&attr.col_name) {
+                Some(value) => literal_cell(value, Some(attr))?,
+                None if attr.autoincrement => {
+                    last_id += 1;
+                    Cell::new(last_id.to_string())
+                }
+                None => match &attr.default {
+                    Some(default) => Cell::new(default.clone()),
+                    None => Cell::null(),
+                },
+            };
+            row.cells.push(cell);
+        }
+    } else {
+        return Err("Cannot mix named and positional values".to_string());
+    }
+
+    //explicit ids move the counter forward so generated ones never collide with them
+    for (cell, attr) in row.cells.iter().zip(schema.attributes.iter()) {
+        if attr.autoincrement
+            && let Ok(id) = cell.value.parse::<i32>()
+        {
+            last_id = last_id.max(id);
+        }
+    }
+    Ok((row, last_id))
+}
 
-    match (cell_num, val_num) {
-        (Ok(cn), Ok(vn)) => match op {
-            Operation::Equal => cn == vn,
-            Operation::Grt => cn > vn,
-            Operation::Les => cn < vn,
-            Operation::GrtEq => cn >= vn,
-            Operation::LesEq => cn <= vn,
-            Operation::NotEq => cn != vn,
-        },
-        _ => match op {
-            Operation::Equal => cell.value == val,
-            Operation::NotEq => cell.value != val,
-            _ => false,
+//a typed `null` is null, a bound value is null only when it is `Value::Null` and has to be
+//of the type of the column it goes in
+fn literal_cell(value: Expr, attr: Option<&Attr>) -> Result<Cell, String> {
+    match value {
+        Expr::Literal(value) if value == "null" => Ok(Cell::null()),
+        Expr::Literal(value) => Ok(Cell::new(value)),
+        Expr::Value(value) => match attr {
+            Some(attr) if !value.fits(attr.datatype) => Err(format!(
+                "Column {} expects {:?}, got {:?}",
+                attr.col_name, attr.datatype, value
+            )),
+            _ => Ok(value.cell()),
         },
+        param => Err(unbound(&param)),
     }
 }
 
-pub fn execute(ast: AstNode) {
-    match ast {
-        AstNode::MakeRDB { name } => {
-            let dir = "Databases";
-            fs::create_dir_all(dir).expect("failed to create Databases directory");
+fn check_validity(row: &Row, attributes: &[Attr]) -> Result<(), String> {
+    if row.cells.len() != attributes.len() {
+        Err(format!(
+            "Column count mismatch: expected {}, got {}",
+            attributes.len(),
+            row.cells.len()
+        ))
+    } else {
+        row.cells
+            .iter()
+            .zip(attributes.iter())
+            .filter(|(cell, _)| !cell.null)
+            .try_for_each(|(cell, attr)| check_value(&cell.value, attr))
+    }
+}
 
-            let path = format!("Databases/{}.rdb", name);
-            let mut data_file = File::create(&path).expect("creation failed");
-
-            let db = db_initialise(name.clone());
-            let db_buf = rmp_serde::to_vec(&db).unwrap();
-            data_file.write_all(&db_buf).unwrap();
-
-            data_file.seek(SeekFrom::Start(4096)).unwrap();
-            let tbl = table_initialise();
-            let tbl_buf = rmp_serde::to_vec(&tbl).unwrap();
-            data_file
-                .write_all(&tbl_buf)
-                .expect("something went wrong with initialising a database");
-
-            print!("New database {} created and selected!\n", name);
-            match ActiveDataBase::open(&name) {
-                Ok(active) => {
-                    let mut db_guard = ACTIVE_DB.lock().unwrap();
-                    *db_guard = Some(active);
-                }
-                Err(_) => {
-                    panic!("Problem opening the data file.");
+//where-clauses and check constraints share this: every condition has to hold for the row
+fn satisfies(
+    row: &Row,
+    attributes: &[Attr],
+    conditions: &[Condition],
+    functions: &Registry,
+) -> Result<bool, String> {
+    for condition in conditions {
+        let Some(left) = &condition.left else {
+            return Err("Exists subquery was not run".to_string());
+        };
+        let c = evaluate(left, row, attributes, functions)?;
+        let kind = datatype(left, attributes, functions)?;
+        let holds = match &condition.test {
+            Test::Compare(op, right) => {
+                let r = evaluate_operand(right, row, attributes, functions)?;
+                !r.null && operation(&c, kind, *op, r.value)
+            }
+            Test::In(values) => {
+                let mut found = false;
+                for value in values {
+                    let v = evaluate(value, row, attributes, functions)?;
+                    found |= !v.null && operation(&c, kind, Operation::Equal, v.value);
                 }
+                found
             }
-        }
-
-        AstNode::OpenRDB { name } => match ActiveDataBase::open(&name) {
-            Ok(active) => {
-                let mut db_guard = ACTIVE_DB.lock().unwrap();
-                *db_guard = Some(active);
-                println!("Opened database: {}", name);
+            Test::Between(low, high) => {
+                let low = evaluate(low, row, attributes, functions)?;
+                let high = evaluate(high, row, attributes, functions)?;
+                !low.null
+                    && !high.null
+                    && operation(&c, kind, Operation::GrtEq, low.value)
+                    && operation(&c, kind, Operation::LesEq, high.value)
             }
-            Err(_) => {
-                println!(
-                    "Problem opening the data file. Create a new database!\nType 'help' to see how"
-                );
+            Test::InPick(_) | Test::Exists(_) => {
+                return Err(format!("Subquery on {} was not run", left));
             }
-        },
-
-        AstNode::MakeTable { name, columns } => {
-            let db_guard = ACTIVE_DB.lock().unwrap();
-            if let Some(active_db) = &*db_guard {
-                println!("Database: {}", active_db.active_db.name);
-                let path = format!("Databases/{}.rdb", active_db.active_db.name);
+        };
+        if !holds {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
 
-                let mut data_file = File::open(&path).unwrap(); //open databse
-                data_file.seek(SeekFrom::Start(0)).unwrap();
-                //first read Database structure and update number of tables
-                let mut db = vec![0u8; PAGE_SIZE];
-                let bytes_read = data_file.read(&mut db).unwrap();
-                db.truncate(bytes_read);
+fn parse_check(check: &str) -> Vec<Condition> {
+    let mut parsed = MyParser::parse(Rule::condition, check).expect("stored check does not parse");
+    build_conditions(parsed.next().unwrap())
+}
 
-                let mut decodeddb: Database = rmp_serde::from_slice(&db).unwrap();
+fn check_constraints(row: &Row, attributes: &[Attr], functions: &Registry) -> Result<(), String> {
+    for (cell, attr) in row.cells.iter().zip(attributes.iter()) {
+        //like SQL, a null column passes its own check
+        if cell.null {
+            continue;
+        }
+        if let Some(check) = &attr.check
+            && !satisfies(row, attributes, &parse_check(check), functions)?
+        {
+            return Err(format!(
+                "Check ({}) failed for column {}",
+                check, attr.col_name
+            ));
+        }
+    }
+    Ok(())
+}
 
-                let number_of_table = decodeddb.tables;
+//a value compares with a cell the way the cell's column orders, so `where`, `order by`,
+//min and max agree: numbers by value and strings lexicographically, `'10' < '9'`
+fn operation(cell: &Cell, datatype: DataType, op: Operation, val: String) -> bool {
+    if cell.null {
+        return false;
+    }
+    if let Some(matched) = pattern_match(&cell.value, op, &val) {
+        return matched;
+    }
+    holds(compare_cells(cell, &Cell::new(val), datatype), op)
+}
 
-                decodeddb.tables += 1; //update number of tables
+fn decimal(value: &str) -> Option<f64> {
+    if value
+        .bytes()
+        .all(|b| b.is_ascii_digit() || b == b'.' || b == b'-')
+    {
+        value.parse().ok()
+    } else {
+        None
+    }
+}
 
-                let mut buf = Vec::new();
-                data_file.read_to_end(&mut buf).unwrap();
-                let mut table_new: TableSchema = TableSchema {
-                    name: name.to_string(),
-                    attributes: Vec::new(),
-                };
+fn holds(order: std::cmp::Ordering, op: Operation) -> bool {
+    use std::cmp::Ordering::{Equal, Greater, Less};
+    match op {
+        Operation::Equal => order == Equal,
+        Operation::Grt => order == Greater,
+        Operation::Les => order == Less,
+        Operation::GrtEq => matches!(order, Greater | Equal),
+        Operation::LesEq => matches!(order, Less | Equal),
+        Operation::NotEq => order != Equal,
+        //pattern operations never reach a comparison
+        _ => false,
+    }
+}
 
-                for (col_name, col_type) in columns {
-                    //println!("Column: {} Type: {}", col_name, col_type);
-                    let datatype = match col_type.to_lowercase().as_str() {
-                        "int" => DataType::Int,
-                        "string" => DataType::String,
-                        "bool" => DataType::Bool,
-                        _ => panic!("Unknown data type: {}", col_type),
-                    };
-                    let new_attr: Attr = Attr {
-                        col_name: col_name,
-                        datatype: datatype,
-                    };
-                    table_new.attributes.push(new_attr);
-                }
+//columns given a value by the insert, positional inserts give all of them
+fn assigned_columns(values: &[Assignment], attributes: &[Attr]) -> Vec<bool> {
+    if values.iter().all(|v| v.column.is_none()) {
+        return vec![true; attributes.len()];
+    }
+    attributes
+        .iter()
+        .map(|a| {
+            values
+                .iter()
+                .any(|v| v.column.as_deref() == Some(a.col_name.as_str()))
+        })
+        .collect()
+}
 
-                //update table details
-                decodeddb.table_details.push(table_new);
+struct Resolved {
+    inserts: Vec<Row>,
+    replaces: Vec<(String, Row)>, //key of a stored row and the row taking its place
+    ignored: usize,
+}
 
-                let mut data_file = OpenOptions::new().write(true).open(&path).unwrap();
-                let newentry = rmp_serde::to_vec(&decodeddb).unwrap();
-                data_file.write_all(&newentry).expect("write failed");
+//splits a batch for a keyed table into new rows and rows replacing stored ones,
+//a later row of the batch with the same key is treated like a conflict with the earlier one
+fn resolve_conflicts(
+    data_file: &mut File,
+    index: &KeyIndex,
+    rows: Vec<(Row, Vec<bool>)>,
+    key_col: usize,
+    attributes: &[Attr],
+    on_conflict: Option<Conflict>,
+) -> Result<Resolved, String> {
+    let datatype = attributes[key_col].datatype;
+    let mut inserts: Vec<Row> = Vec::new();
+    let mut replaces: Vec<(String, Row)> = Vec::new();
+    let mut pending: HashMap<String, (bool, usize)> = HashMap::new(); //key -> (stored, position)
+    let mut pages: HashMap<i32, TableRow> = HashMap::new();
+    let mut ignored = 0;
+
+    for (row, assigned) in rows {
+        let cell = &row.cells[key_col];
+        if cell.null {
+            return Err(format!(
+                "Key column {} cannot be null",
+                attributes[key_col].col_name
+            ));
+        }
+        let key = key_of(cell, datatype);
+
+        let existing = match pending.get(&key) {
+            Some(&(true, pos)) => Some(&mut replaces[pos].1),
+            Some(&(false, pos)) => Some(&mut inserts[pos]),
+            None => None,
+        };
+        if let Some(existing) = existing {
+            match on_conflict {
+                None => return Err(format!("Duplicate key {}", key)),
+                Some(Conflict::Ignore) => ignored += 1,
+                Some(Conflict::Replace) => *existing = row,
+                Some(Conflict::Update) => merge_row(existing, row, &assigned),
+            }
+            continue;
+        }
 
-                //update table information
-                let mut data_file = File::open(&path).unwrap();
-                data_file.seek(SeekFrom::Start(4096)).unwrap();
-                let mut table_info = vec![0u8; PAGE_SIZE];
-                let table_bytes = data_file.read(&mut table_info).unwrap();
-                table_info.truncate(table_bytes);
+        match index.keys.get(&key) {
+            None => {
+                pending.insert(key, (false, inserts.len()));
+                inserts.push(row);
+            }
+            Some(location) => match on_conflict {
+                None => return Err(format!("Duplicate key {}", key)),
+                Some(Conflict::Ignore) => ignored += 1,
+                Some(action) => {
+                    let mut stored = cached_page(data_file, &mut pages, location.data_page)?
+                        .rows
+                        .iter()
+                        .find(|r| key_of(&r.cells[key_col], datatype) == key)
+                        .cloned()
+                        .ok_or_else(|| format!("Key {} is indexed but its row is missing", key))?;
+                    if action == Conflict::Update {
+                        merge_row(&mut stored, row, &assigned);
+                    } else {
+                        stored = row;
+                    }
+                    pending.insert(key.clone(), (true, replaces.len()));
+                    replaces.push((key, stored));
+                }
+            },
+        }
+    }
+    Ok(Resolved {
+        inserts,
+        replaces,
+        ignored,
+    })
+}
 
-                let mut decodedtable: TableInfo = rmp_serde::from_slice(&table_info).unwrap();
+//`on conflict update` only overwrites the columns the insert named
+fn merge_row(existing: &mut Row, row: Row, assigned: &[bool]) {
+    for ((cell, new_cell), &given) in existing.cells.iter_mut().zip(row.cells).zip(assigned) {
+        if given {
+            *cell = new_cell;
+        }
+    }
+}
 
-                decodedtable.tables.insert(name, number_of_table + 2);
+//the rows stored under the given keys, read through the key index of the table instead of
+//scanning it. each key is looked up once and keys that are not stored find nothing
+fn lookup_keys(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    table: &str,
+    key_col: usize,
+    datatype: DataType,
+    keys: &[String],
+) -> Result<Vec<Row>, String> {
+    let Some(index) = read_key_index(data_file, table_info, table)? else {
+        return Ok(Vec::new());
+    };
+    let mut seen = HashSet::new();
+    let mut pages: HashMap<i32, TableRow> = HashMap::new();
+    let mut rows = Vec::new();
+    for key in keys {
+        let key = key_of(&Cell::new(key.clone()), datatype);
+        if !seen.insert(key.clone()) {
+            continue;
+        }
+        let Some(location) = index.keys.get(&key) else {
+            continue;
+        };
+        rows.extend(
+            cached_page(data_file, &mut pages, location.data_page)?
+                .rows
+                .iter()
+                .find(|r| key_of(&r.cells[key_col], datatype) == key)
+                .cloned(),
+        );
+    }
+    Ok(rows)
+}
 
-                let mut data_file = OpenOptions::new().write(true).open(&path).unwrap();
-                data_file.seek(SeekFrom::Start(4096)).unwrap();
+//a page of rows, read from the data file the first time it is asked for
+fn cached_page<'p>(
+    data_file: &mut File,
+    pages: &'p mut HashMap<i32, TableRow>,
+    page: i32,
+) -> Result<&'p TableRow, String> {
+    Ok(match pages.entry(page) {
+        Entry::Occupied(cached) => cached.into_mut(),
+        Entry::Vacant(missing) => missing.insert(read_rows_page(data_file, page)?),
+    })
+}
 
-                let table_buf = rmp_serde::to_vec(&decodedtable).unwrap();
-                data_file.write_all(&table_buf).expect("write failed");
+//int cells compare by value, so "007" and "7" are the same key
+fn key_of(cell: &Cell, datatype: DataType) -> String {
+    match (datatype, cell.value.parse::<i32>()) {
+        (DataType::Int, Ok(n)) => n.to_string(),
+        _ => cell.value.clone(),
+    }
+}
 
-                println!("New table created");
-            } else {
-                println!("No database is active.");
+//every `references` column of the rows must match a value in the parent column,
+//each parent column is read once for the whole batch
+fn check_references(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    db: &Database,
+    rows: &[Row],
+    attributes: &[Attr],
+) -> Result<(), String> {
+    for (col, attr) in attributes.iter().enumerate() {
+        let Some(fk) = &attr.references else {
+            continue;
+        };
+        let parent_col = db
+            .table_details
+            .iter()
+            .find(|t| t.name == fk.table)
+            .and_then(|t| t.attributes.iter().position(|a| a.col_name == fk.column));
+        let Some(parent_col) = parent_col else {
+            return Err(format!(
+                "Parent table {} no longer has column {}",
+                fk.table, fk.column
+            ));
+        };
+
+        let parent_keys: HashSet<String> = scan_table(data_file, table_info, &fk.table)?
+            .iter()
+            .filter_map(|r| r.cells.get(parent_col))
+            .filter(|c| !c.null)
+            .map(|c| key_of(c, attr.datatype))
+            .collect();
+        for row in rows {
+            let cell = &row.cells[col];
+            if !cell.null && !parent_keys.contains(&key_of(cell, attr.datatype)) {
+                return Err(format!(
+                    "Column {} value {} has no match in {}({})",
+                    attr.col_name, cell.value, fk.table, fk.column
+                ));
             }
         }
+    }
+    Ok(())
+}
 
-        AstNode::Add { table, values } => {
-            println!("Insert into table: {}", table);
+//replacing rows of a parent table restricts like SQL: a value of a referenced column may
+//only go away when no child row refers to it anymore. `replaced` are the keys of the stored
+//rows taken out, `rows` the rows that are put in
+fn check_referenced(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    db: &Database,
+    table: &str,
+    replaced: &HashSet<&str>,
+    rows: &[Row],
+) -> Result<(), String> {
+    let Some(schema) = db.table_details.iter().find(|t| t.name == table) else {
+        return Ok(());
+    };
+    let mut children = Vec::new(); //(child table, child column, parent column)
+    for child in &db.table_details {
+        for (col, attr) in child.attributes.iter().enumerate() {
+            let Some(fk) = attr.references.as_ref().filter(|fk| fk.table == table) else {
+                continue;
+            };
+            if let Some(parent_col) = schema
+                .attributes
+                .iter()
+                .position(|a| a.col_name == fk.column)
+            {
+                children.push((child, col, parent_col));
+            }
+        }
+    }
+    if children.is_empty() {
+        return Ok(());
+    }
 
-            let db_guard = ACTIVE_DB.lock().unwrap();
-            if let Some(active_db) = &*db_guard {
-                println!("Database: {}", active_db.active_db.name);
+    let key_col = schema.attributes.iter().position(|a| a.key).unwrap();
+    let datatype = schema.attributes[key_col].datatype;
+    let (old, kept): (Vec<Row>, Vec<Row>) = scan_table(data_file, table_info, table)?
+        .into_iter()
+        .partition(|r| replaced.contains(key_of(&r.cells[key_col], datatype).as_str()));
+
+    for (child, col, parent_col) in children {
+        let datatype = schema.attributes[parent_col].datatype;
+        let values = |rows: &[Row]| -> HashSet<String> {
+            rows.iter()
+                .map(|r| &r.cells[parent_col])
+                .filter(|c| !c.null)
+                .map(|c| key_of(c, datatype))
+                .collect()
+        };
+        let remaining: HashSet<String> = values(&kept).union(&values(rows)).cloned().collect();
+        let gone: HashSet<String> = values(&old).difference(&remaining).cloned().collect();
+        if gone.is_empty() {
+            continue;
+        }
+        //a table referencing itself is checked against the rows it is left with
+        let child_rows = if child.name == table {
+            kept.iter().chain(rows).cloned().collect()
+        } else {
+            scan_table(data_file, table_info, &child.name)?
+        };
+        if let Some(cell) = child_rows
+            .iter()
+            .map(|r| &r.cells[col])
+            .find(|c| !c.null && gone.contains(&key_of(c, datatype)))
+        {
+            return Err(format!(
+                "{}({}) value {} is still referenced by {}({})",
+                table,
+                schema.attributes[parent_col].col_name,
+                cell.value,
+                child.name,
+                child.attributes[col].col_name
+            ));
+        }
+    }
+    Ok(())
+}
 
-                let path = format!("Databases/{}.rdb", active_db.active_db.name);
-                let mut data_file = OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(&path)
-                    .unwrap();
+//runs a statement against the database `active` has open, `make rdb` and `open rdb`
+//replace it
+pub fn execute(ast: AstNode, active: &mut Option<ActiveDataBase>) -> Result<QueryResult, String> {
+    match ast {
+        AstNode::MakeRDB { name } => {
+            let path = format!("Databases/{}.rdb", name);
+            create_database(Path::new(&path), name.clone())
+                .map_err(|e| format!("Problem creating the data file: {}", e))?;
+            let opened = ActiveDataBase::open(&path)
+                .map_err(|e| format!("Problem opening the data file: {}", e))?;
+            *active = Some(opened);
+            Ok(QueryResult::done(
+                format!("New database {} created and selected!", name),
+                0,
+            ))
+        }
 
-                data_file.seek(SeekFrom::Start(0)).unwrap();
-                let mut db_page = vec![0u8; PAGE_SIZE];
-                let bytes_read = data_file.read(&mut db_page).unwrap();
-                db_page.truncate(bytes_read);
-                let decodeddb: Database = rmp_serde::from_slice(&db_page).unwrap();
+        AstNode::OpenRDB { name } => {
+            let opened = ActiveDataBase::open(format!("Databases/{}.rdb", name)).map_err(|_| {
+                "Problem opening the data file. Create a new database!\nType 'help' to see how"
+                    .to_string()
+            })?;
+            *active = Some(opened);
+            Ok(QueryResult::done(format!("Opened database: {}", name), 0))
+        }
 
-                data_file.seek(SeekFrom::Start(4096)).unwrap();
-                let mut table_page = vec![0u8; PAGE_SIZE];
-                let table_bytes = data_file.read(&mut table_page).unwrap();
-                table_page.truncate(table_bytes);
-                let decodedtable: TableInfo = rmp_serde::from_slice(&table_page).unwrap();
+        AstNode::MakeTable { name, columns } => {
+            let active_db = active_database(active)?;
+            let path = &active_db.path;
 
-                if let Some(&pointer) = decodedtable.tables.get(&table) {
-                    let table_offset = (pointer as u64) * 4096;
-                    data_file.seek(SeekFrom::Start(table_offset)).unwrap();
-                    let mut rows_bytes = vec![0u8; PAGE_SIZE];
-                    let n = data_file.read(&mut rows_bytes).unwrap();
-                    rows_bytes.truncate(n);
+            let mut data_file = open_data_file(path)?; //open databse
+            //first read Database structure and update number of tables
+            let mut decodeddb = read_database(&mut data_file)?;
 
-                    let mut all_rows: TableRow = if n == 0 {
-                        TableRow { rows: Vec::new() }
+            if decodeddb.views.iter().any(|v| v.name == name) {
+                return Err(format!("{} is already a view, table not created", name));
+            }
+            decodeddb.tables += 1; //update number of tables
+
+            let table_new: TableSchema = TableSchema {
+                name: name.to_string(),
+                attributes: columns,
+                last_id: 0,
+                rows: 0,
+            };
+
+            //foreign keys must point at an existing column of the same type, defaults and checks must fit the table
+            for attr in &table_new.attributes {
+                if let Some(fk) = &attr.references {
+                    let parent = if fk.table == name {
+                        Some(&table_new)
                     } else {
-                        match rmp_serde::from_slice(&rows_bytes) {
-                            Ok(r) => r,
-                            Err(_) => TableRow { rows: Vec::new() },
-                        }
+                        decodeddb.table_details.iter().find(|t| t.name == fk.table)
                     };
-
-                    let mut new_row = Row { cells: Vec::new() };
-                    for val in values {
-                        new_row.cells.push(Cell { value: val });
-                    }
-
-                    let details = &decodeddb.table_details;
-                    if let Some(schema) = details.iter().find(|t| t.name == table) {
-                        let attributes = &schema.attributes;
-
-                        // for attr in attributes {
-                        //     println!("{} ({:?})", attr.col_name, attr.datatype);
-                        // }
-
-                        if check_validity(&new_row, attributes) {
-                            all_rows.rows.push(new_row);
-                            let updated_bytes = rmp_serde::to_vec(&all_rows).unwrap();
-
-                            data_file.seek(SeekFrom::Start(table_offset)).unwrap();
-                            data_file
-                                .set_len(table_offset + updated_bytes.len() as u64)
-                                .unwrap();
-                            data_file.write_all(&updated_bytes).expect("write failed");
-                            println!("1 Row added");
-                        } else {
-                            println!("Datatype mismatch, Row not added");
+                    let parent_attr =
+                        parent.and_then(|t| t.attributes.iter().find(|a| a.col_name == fk.column));
+                    match parent_attr {
+                        None => {
+                            return Err(format!(
+                                "Column {} references unknown column {}({}), table not created",
+                                attr.col_name, fk.table, fk.column
+                            ));
                         }
-                    } else {
-                        panic!("Table {} not found in table_details", table);
+                        Some(p) if p.datatype != attr.datatype => {
+                            return Err(format!(
+                                "Column {} is {:?} but {}({}) is {:?}, table not created",
+                                attr.col_name, attr.datatype, fk.table, fk.column, p.datatype
+                            ));
+                        }
+                        Some(_) => {}
+                    }
+                    if fk.on_delete == OnDelete::SetNull && attr.key {
+                        return Err(format!(
+                            "Key column {} can not be set null on delete, table not created",
+                            attr.col_name
+                        ));
                     }
-                } else {
-                    panic!("Table not available, create a table before adding records!");
                 }
-            } else {
-                println!("No database is active.");
-            }
-        }
-
-        AstNode::Pick { table, columns } => {
-            let db_guard = ACTIVE_DB.lock().unwrap();
-            if let Some(active_db) = &*db_guard {
-                println!("Database: {}\n", active_db.active_db.name);
-                println!("Pick from table: {}", table);
-
-                let path = format!("Databases/{}.rdb", active_db.active_db.name);
-                let mut data_file = OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(&path)
-                    .unwrap();
-                data_file.seek(SeekFrom::Start(0)).unwrap();
-                let mut db_page = vec![0u8; PAGE_SIZE];
-                let bytes_read = data_file.read(&mut db_page).unwrap();
-                db_page.truncate(bytes_read);
-                let decodeddb: Database = rmp_serde::from_slice(&db_page).unwrap();
-                let alltables = decodeddb.table_details;
-
-                let mut i: i32 = -1;
-                for k in alltables.iter() {
-                    i += 1;
-                    if k.name == table {
-                        break;
+                if let Some(default) = &attr.default {
+                    check_value(default, attr)
+                        .map_err(|e| format!("Invalid default: {}, table not created", e))?;
+                }
+                if attr.key && table_new.attributes.iter().filter(|a| a.key).count() > 1 {
+                    return Err(format!(
+                        "Table {} can only have one key column, table not created",
+                        name
+                    ));
+                }
+                if attr.autoincrement && attr.datatype != DataType::Int {
+                    return Err(format!(
+                        "Autoincrement column {} must be int, table not created",
+                        attr.col_name
+                    ));
+                }
+                if let Some(check) = &attr.check {
+                    for condition in parse_check(check) {
+                        if let Err(e) =
+                            check_condition(&condition, &table_new.attributes, &active_db.functions)
+                        {
+                            return Err(format!(
+                                "Check on column {}: {}, table not created",
+                                attr.col_name, e
+                            ));
+                        }
                     }
                 }
+            }
 
-                let mut indexes: Vec<usize> = Vec::new();
+            //update table details
+            decodeddb.table_details.push(table_new);
+
+            write_database(&mut data_file, &decodeddb)
+                .map_err(|e| format!("{}, table not created", e))?;
+
+            //update table information, a table whose pages or directory entry can not be
+            //written is taken back out of the catalog
+            let mut decodedtable = read_table_info(&mut data_file)?;
+            let keyed = decodeddb
+                .table_details
+                .last()
+                .is_some_and(|t| t.attributes.iter().any(|a| a.key));
+            let created = create_table(&mut data_file, &mut decodedtable, &name, keyed)
+                .and_then(|_| write_table_info(&mut data_file, &decodedtable));
+            if let Err(e) = created {
+                decodeddb.table_details.pop();
+                decodeddb.tables -= 1;
+                write_database(&mut data_file, &decodeddb)?;
+                return Err(format!("{}, table not created", e));
+            }
 
-                if i == -1 {
-                    print!("Table not found")
-                } else {
-                    if let Some(table_picked) = alltables.get((i) as usize) {
-                        for (index, attribute) in table_picked.attributes.iter().enumerate() {
-                            if columns.contains(&attribute.col_name) {
-                                indexes.push(index);
-                            }
-                        }
-                    } else {
-                        println!("No table found at index {}", i);
+            Ok(QueryResult::done("New table created".to_string(), 0))
+        }
+
+        AstNode::Add {
+            table,
+            rows,
+            on_conflict,
+        } => {
+            let active_db = active_database(active)?;
+            let mut data_file = open_data_file(&active_db.path)?;
+
+            let mut decodeddb = read_database(&mut data_file)?;
+            let mut decodedtable = read_table_info(&mut data_file)?;
+
+            let schema = decodeddb.table_details.iter().find(|t| t.name == table);
+            let Some(schema) = schema.filter(|_| decodedtable.tables.contains_key(&table)) else {
+                return Err(format!(
+                    "Table {} not found, create a table before adding records!",
+                    table
+                ));
+            };
+            let attributes = &schema.attributes;
+            let key_col = attributes.iter().position(|a| a.key);
+            if on_conflict.is_some() && key_col.is_none() {
+                return Err(format!(
+                    "Table {} has no key column to detect conflicts on",
+                    table
+                ));
+            }
+
+            //the whole batch is validated before anything is written
+            let mut last_id = schema.last_id;
+            let mut new_rows = Vec::with_capacity(rows.len());
+            for (n, values) in rows.into_iter().enumerate() {
+                let assigned = assigned_columns(&values, attributes);
+                let new_row = match build_row(values, schema, last_id) {
+                    Ok((new_row, id)) => {
+                        last_id = id;
+                        new_row
                     }
+                    Err(e) => return Err(format!("{} in row {}, no rows added", e, n + 1)),
+                };
+                check_validity(&new_row, attributes).map_err(|e| {
+                    format!("Datatype mismatch: {} in row {}, no rows added", e, n + 1)
+                })?;
+                new_rows.push((new_row, assigned));
+            }
+
+            let mut index = read_key_index(&mut data_file, &decodedtable, &table)?;
+            let resolved = match (key_col, &index) {
+                (Some(key_col), Some(index)) => resolve_conflicts(
+                    &mut data_file,
+                    index,
+                    new_rows,
+                    key_col,
+                    attributes,
+                    on_conflict,
+                ),
+                _ => Ok(Resolved {
+                    inserts: new_rows.into_iter().map(|(row, _)| row).collect(),
+                    replaces: Vec::new(),
+                    ignored: 0,
+                }),
+            };
+            let resolved = match resolved {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    return Err(format!("{}, no rows added", e));
                 }
+            };
+
+            let final_rows: Vec<Row> = resolved
+                .inserts
+                .iter()
+                .chain(resolved.replaces.iter().map(|(_, row)| row))
+                .cloned()
+                .collect();
+            for (n, row) in final_rows.iter().enumerate() {
+                check_constraints(row, attributes, &active_db.functions)
+                    .map_err(|e| format!("{} in row {}, no rows added", e, n + 1))?;
+            }
+            check_references(
+                &mut data_file,
+                &decodedtable,
+                &decodeddb,
+                &final_rows,
+                attributes,
+            )
+            .map_err(|e| format!("Foreign key violation: {}, no rows added", e))?;
+            if !resolved.replaces.is_empty() {
+                let replaced: HashSet<&str> = resolved
+                    .replaces
+                    .iter()
+                    .map(|(key, _)| key.as_str())
+                    .collect();
+                check_referenced(
+                    &mut data_file,
+                    &decodedtable,
+                    &decodeddb,
+                    &table,
+                    &replaced,
+                    &final_rows,
+                )
+                .map_err(|e| format!("Foreign key violation: {}, no rows added", e))?;
+            }
 
-                data_file.seek(SeekFrom::Start(PAGE_SIZE as u64)).unwrap();
-                let mut table_page = vec![0u8; PAGE_SIZE];
-                let table_bytes = data_file.read(&mut table_page).unwrap();
-                table_page.truncate(table_bytes);
-                let decodedtable: TableInfo = rmp_serde::from_slice(&table_page).unwrap();
-                let pointer = decodedtable.tables[&table];
-
-                let table_offset = (pointer as u64) * 4096;
-                data_file.seek(SeekFrom::Start(table_offset)).unwrap();
-                let mut rows_bytes = vec![0u8; PAGE_SIZE];
-                let n = data_file.read(&mut rows_bytes).unwrap();
-                rows_bytes.truncate(n);
-                let table_rows: TableRow = rmp_serde::from_slice(&rows_bytes).unwrap();
-
-                for name in &columns {
-                    print!("{:<10}", name);
+            let added = resolved.inserts.len();
+            let replaced = resolved.replaces.len();
+            let key_type = key_col.map(|k| (k, attributes[k].datatype));
+
+            //the autoincrement counter and the row count are saved once the rows are
+            //stored, a catalog that could not take them refuses the rows up front so an
+            //id is never handed out twice
+            let schema = decodeddb
+                .table_details
+                .iter_mut()
+                .find(|t| t.name == table)
+                .unwrap();
+            let counted = schema.last_id != last_id || added > 0;
+            schema.last_id = last_id;
+            schema.rows += added;
+            check_database(&decodeddb).map_err(|e| format!("{}, no rows added", e))?;
+
+            //replaced rows are rewritten on their own page, the ones that no longer fit
+            //there are moved to the end of the table together with the new rows
+            let mut moved = Vec::new();
+            if let (Some(index), Some((key_col, datatype))) = (&index, key_type) {
+                let mut by_page: HashMap<i32, HashMap<String, Row>> = HashMap::new();
+                for (key, row) in resolved.replaces {
+                    by_page
+                        .entry(index.keys[&key].data_page)
+                        .or_default()
+                        .insert(key, row);
                 }
-                println!();
-                println!("{}", "-".repeat(columns.len() * 10));
-                for (_index, element) in table_rows.rows.iter().enumerate() {
-                    for &col in &indexes {
-                        if let Some(cell) = element.cells.get(col) {
-                            print!("{:<10}", cell.value);
+                for (page, mut replacements) in by_page {
+                    let mut table_page = read_rows_page(&mut data_file, page)?;
+                    for row in table_page.rows.iter_mut() {
+                        if let Some(new_row) =
+                            replacements.remove(&key_of(&row.cells[key_col], datatype))
+                        {
+                            *row = new_row;
                         }
                     }
-                    println!();
+                    while !fits_page(&table_page) {
+                        moved.push(table_page.rows.pop().unwrap());
+                    }
+                    write_rows_page(&mut data_file, page, &table_page)?;
                 }
-            } else {
-                println!("No database is active.");
             }
+
+            let mut to_append = resolved.inserts;
+            to_append.extend(moved);
+            let keys: Vec<Option<String>> = to_append
+                .iter()
+                .map(|row| key_type.map(|(k, datatype)| key_of(&row.cells[k], datatype)))
+                .collect();
+            let pages = match append_rows(&mut data_file, &mut decodedtable, &table, to_append) {
+                Ok(pages) => pages,
+                Err(e) => {
+                    return Err(format!("{}, no rows added", e));
+                }
+            };
+            let mut done = vec![format!(
+                "{} Row{} added",
+                added,
+                if added == 1 { "" } else { "s" }
+            )];
+            if let Some(index) = index.as_mut() {
+                let mut entries = Vec::new();
+                let mut moved_keys = Vec::new();
+                for (n, (key, page)) in keys.into_iter().zip(pages).enumerate() {
+                    let key = key.unwrap();
+                    if n < added {
+                        entries.push(KeyEntry { key, page });
+                    } else {
+                        moved_keys.push((key, page));
+                    }
+                }
+                add_keys(&mut data_file, &decodedtable, index, entries)?;
+                move_keys(&mut data_file, index, moved_keys)?;
+            }
+
+            if replaced > 0 {
+                done.push(format!("{} replaced", replaced));
+            }
+            if resolved.ignored > 0 {
+                done.push(format!("{} ignored", resolved.ignored));
+            }
+
+            if counted {
+                write_database(&mut data_file, &decodeddb)?;
+            }
+            Ok(QueryResult::done(done.join("\n"), added + replaced))
+        }
+
+        AstNode::MakeView { name, query } => make_view(active_database(active)?, name, query),
+
+        AstNode::Analyze { table } => analyze(active_database(active)?, table),
+
+        AstNode::Remove { table, conditions } => {
+            remove(active_database(active)?, table, conditions)
+        }
+
+        pick_ast => pick(active_database(active)?, pick_ast),
+    }
+}
+
+//resolves the requested columns against the schema: `*` expands to every column in schema
+//order, names keep the order they were asked for and may be repeated. every entry is the
+//header it shows with the expression computing it
+fn project(attributes: &[Attr], columns: &[Selective]) -> Result<Vec<(String, Expr)>, String> {
+    let mut picked = Vec::new();
+    for column in columns {
+        match column {
+            Selective::Column(name) if name == "*" => picked.extend(
+                attributes
+                    .iter()
+                    .map(|a| (a.col_name.clone(), Expr::Column(a.col_name.clone()))),
+            ),
+            Selective::Column(name) => {
+                if !attributes.iter().any(|a| &a.col_name == name) {
+                    return Err(format!("Unknown column {}", name));
+                }
+                picked.push((name.clone(), Expr::Column(name.clone())));
+            }
+            Selective::Aggregate { .. } => {
+                return Err(format!("{} cannot be mixed with plain columns", column));
+            }
+            Selective::Expr { expr, .. } => {
+                picked.push((column.to_string(), expr.clone()));
+            }
+        }
+    }
+    Ok(picked)
+}
+
+//resolves the pick list of a grouped pick against the grouped rows, together with every
+//aggregate that only having or order by refer to
+fn group_columns(
+    attributes: &[Attr],
+    columns: &[Selective],
+    clauses: &Clauses,
+) -> Result<(Grouping, Vec<(String, Expr)>), String> {
+    let mut grouping = Grouping::new(&clauses.group_by, attributes)?;
+    let mut picked = Vec::new();
+    for column in columns {
+        match column {
+            Selective::Column(name) if name == "*" => {
+                return Err("* cannot be picked with group by or aggregates".to_string());
+            }
+            Selective::Column(_) | Selective::Aggregate { .. } => {
+                let name = column.to_string();
+                grouping.column(&name, attributes)?;
+                picked.push((name.clone(), Expr::Column(name)));
+            }
+            Selective::Expr { expr, .. } => {
+                for name in expr_columns(expr) {
+                    grouping.column(name, attributes)?;
+                }
+                picked.push((column.to_string(), expr.clone()));
+            }
+        }
+    }
+    for condition in &clauses.having {
+        for name in condition_columns(condition) {
+            grouping.column(name, attributes)?;
+        }
+        //a lone word compared with is a column only when the table or an aggregate has it
+        if let Test::Compare(_, Expr::Column(name)) = &condition.test
+            && (parse_aggregate(name).is_some() || attributes.iter().any(|a| &a.col_name == name))
+        {
+            grouping.column(name, attributes)?;
+        }
+    }
+    //order by may also name a column of the pick list by its alias
+    for key in &clauses.order_by {
+        if !picked.iter().any(|(name, _)| name == &key.column) {
+            grouping.column(&key.column, attributes)?;
+        }
+    }
+    Ok((grouping, picked))
+}
+
+//the columns of a condition have to exist, its operands to have the right types and a
+//regular expression to compile
+fn check_condition(
+    condition: &Condition,
+    attributes: &[Attr],
+    functions: &Registry,
+) -> Result<(), String> {
+    if let Test::InPick(_) | Test::Exists(_) = condition.test {
+        return Err("subqueries are not allowed here".to_string());
+    }
+    if let Some(name) = condition_columns(condition)
+        .into_iter()
+        .find(|name| !attributes.iter().any(|a| &a.col_name == name))
+    {
+        return Err(format!("Unknown column {} in condition", name));
+    }
+    if let Some(left) = &condition.left {
+        let kind = datatype(left, attributes, functions)?;
+        //a bound value has to be of the type it is compared with
+        let bound: Vec<&Value> = match &condition.test {
+            Test::Compare(op, Expr::Value(value)) if !op.is_pattern() => vec![value],
+            Test::In(values) => values
+                .iter()
+                .filter_map(|v| match v {
+                    Expr::Value(value) => Some(value),
+                    _ => None,
+                })
+                .collect(),
+            Test::Between(low, high) => [low, high]
+                .into_iter()
+                .filter_map(|v| match v {
+                    Expr::Value(value) => Some(value),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        if let Some(value) = bound.into_iter().find(|v| !v.fits(kind)) {
+            return Err(format!("{} is {:?}, got {:?}", left, kind, value));
+        }
+    }
+    match &condition.test {
+        Test::Compare(op, Expr::Literal(pattern) | Expr::Column(pattern))
+        | Test::Compare(op, Expr::Value(Value::Text(pattern))) => check_pattern(*op, pattern),
+        Test::Compare(_, right) => datatype(right, attributes, functions).map(|_| ()),
+        Test::In(values) => values
+            .iter()
+            .try_for_each(|v| datatype(v, attributes, functions).map(|_| ())),
+        Test::Between(low, high) => [low, high]
+            .into_iter()
+            .try_for_each(|v| datatype(v, attributes, functions).map(|_| ())),
+        _ => Ok(()),
+    }
+}
+
+fn sort_columns(attributes: &[Attr], order_by: &[SortKey]) -> Result<Vec<SortColumn>, String> {
+    order_by
+        .iter()
+        .map(|key| {
+            let index = attributes
+                .iter()
+                .position(|a| a.col_name == key.column)
+                .ok_or_else(|| format!("Unknown column {} in order by", key.column))?;
+            Ok(SortColumn {
+                index,
+                datatype: attributes[index].datatype,
+                descending: key.descending,
+            })
+        })
+        .collect()
+}
+
+//the query of a view is run once when it is made, so a view that can not be read is never
+//saved
+fn make_view(
+    active_db: &ActiveDataBase,
+    name: String,
+    query: String,
+) -> Result<QueryResult, String> {
+    let mut data_file = open_data_file(&active_db.path)?;
+    let mut decodeddb = read_database(&mut data_file)?;
+    if decodeddb.table_details.iter().any(|t| t.name == name)
+        || decodeddb.views.iter().any(|v| v.name == name)
+    {
+        return Err(format!("{} already exists, view not created", name));
+    }
+    let view = ViewSchema { name, query };
+    let mut source = Source {
+        data_file: &mut data_file,
+        db: &decodeddb,
+        functions: &active_db.functions,
+    };
+    //planning the query checks it, none of its rows are read
+    if let Err(e) = run_view(&mut source, &view) {
+        return Err(format!("{}, view not created", e));
+    }
+    decodeddb.views.push(view);
+    write_database(&mut data_file, &decodeddb).map_err(|e| format!("{}, view not created", e))?;
+    Ok(QueryResult::done("New view created".to_string(), 0))
+}
+
+//recounts the rows of the table, or of every table, into the catalog and saves the
+//statistics of their columns in a page chain of each table
+fn analyze(active_db: &ActiveDataBase, table: Option<String>) -> Result<QueryResult, String> {
+    let mut data_file = open_data_file(&active_db.path)?;
+    let mut decodeddb = read_database(&mut data_file)?;
+    let mut decodedtable = read_table_info(&mut data_file)?;
+    if let Some(table) = &table
+        && !decodeddb.table_details.iter().any(|t| &t.name == table)
+    {
+        return Err(format!("Table {} not found", table));
+    }
+    let mut done = Vec::new();
+    for schema in decodeddb.table_details.iter_mut() {
+        if table.as_ref().is_some_and(|t| t != &schema.name) {
+            continue;
+        }
+        let rows = TableScan::new(&mut data_file, &decodedtable, &schema.name);
+        let (count, stats) = collect_stats(rows, &schema.attributes)?;
+        write_stats(&mut data_file, &mut decodedtable, &schema.name, stats)
+            .map_err(|e| format!("{}, statistics of {} not saved", e, schema.name))?;
+        schema.rows = count;
+        done.push(format!(
+            "Analyzed {}: {} row{}",
+            schema.name,
+            count,
+            if count == 1 { "" } else { "s" }
+        ));
+    }
+    write_database(&mut data_file, &decodeddb)
+        .map_err(|e| format!("{}, row counts not saved", e))?;
+    Ok(QueryResult::done(done.join("\n"), 0))
+}
+
+fn pick(active_db: &ActiveDataBase, ast: AstNode) -> Result<QueryResult, String> {
+    let mut data_file = open_data_file(&active_db.path)?;
+    let decodeddb = read_database(&mut data_file)?;
+    let source = Source {
+        data_file: &mut data_file,
+        db: &decodeddb,
+        functions: &active_db.functions,
+    };
+    let (columns, rows) = run_pick(source, ast)?;
+    QueryResult::rows(&columns, rows)
+}
+
+//the rows of a pick, read lazily from the data file. a page that can not be read gives its
+//error in place of a row
+pub type RowStream<'s> = Box<dyn Iterator<Item = Result<Row, String>> + 's>;
+
+//what a pick reads besides its own text: the data file, the schemas of its tables and the
+//functions it may call
+pub struct Source<'a> {
+    pub data_file: &'a mut File,
+    pub db: &'a Database,
+    pub functions: &'a Registry,
+}
+
+impl Source<'_> {
+    //the same source for a subquery, whose rows are read before the outer pick's
+    fn reborrow(&mut self) -> Source<'_> {
+        Source {
+            data_file: &mut *self.data_file,
+            db: self.db,
+            functions: self.functions,
         }
+    }
+}
 
+//plans a pick, or with `explain` the pick it shows, and returns the columns and the rows
+pub fn run_pick<'s>(
+    source: Source<'s>,
+    ast: AstNode,
+) -> Result<(Vec<Attr>, RowStream<'s>), String> {
+    let (ast, explain) = match ast {
+        AstNode::Explain(pick) => (*pick, true),
+        ast => (ast, false),
+    };
+    let (table, joins, columns, conditions, clauses) = match ast {
+        AstNode::Pick {
+            table,
+            joins,
+            columns,
+            clauses,
+        } => (table, joins, columns, Vec::new(), clauses),
         AstNode::ConditionalPick {
             table,
+            joins,
             columns,
-            att,
-            oper,
-            val,
-        } => {
-            println!("Pick from table: {}", table);
-          
-            let db_guard = ACTIVE_DB.lock().unwrap();
-            if let Some(active_db) = &*db_guard {
-                println!("Database: {}", active_db.active_db.name);
-
-                let path = format!("Databases/{}.rdb", active_db.active_db.name);
-                let mut data_file = OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(&path)
-                    .unwrap();
-                data_file.seek(SeekFrom::Start(0)).unwrap();
-                let mut db_page = vec![0u8; PAGE_SIZE];
-                let bytes_read = data_file.read(&mut db_page).unwrap();
-                db_page.truncate(bytes_read);
-                let decodeddb: Database = rmp_serde::from_slice(&db_page).unwrap();
-                let alltables = decodeddb.table_details;
-
-                let mut i: i32 = -1;
-                for k in alltables.iter() {
-                    i += 1;
-                    if k.name == table {
-                        break;
-                    }
+            conditions,
+            clauses,
+        } => (table, joins, columns, conditions, clauses),
+        _ if explain => return Err("Only picks can be explained".to_string()),
+        _ => return Err("Only picks return rows".to_string()),
+    };
+    query(
+        source, &table, &joins, columns, conditions, clauses, explain,
+    )
+}
+
+//plans a pick and returns the columns it shows and an iterator pulling its rows through
+//the plan, or with `explain` a single plan column with a row for every step
+fn query<'s>(
+    mut source: Source<'s>,
+    table: &str,
+    joins: &[Join],
+    mut columns: Vec<Selective>,
+    conditions: Vec<Condition>,
+    mut clauses: Clauses,
+    explain: bool,
+) -> Result<(Vec<Attr>, RowStream<'s>), String> {
+    //subqueries run first, their rows become value lists
+    let mut conditions = run_subqueries(&mut source, conditions)?;
+    let having = std::mem::take(&mut clauses.having);
+    clauses.having = run_subqueries(&mut source, having)?;
+    let names = std::iter::once(table).chain(joins.iter().map(|j| j.table.as_str()));
+    let Views {
+        schemas: view_schemas,
+        rows: view_rows,
+    } = expand_views(&mut source, names)?;
+    let Source {
+        data_file,
+        db,
+        functions,
+    } = source;
+
+    let tables = picked_tables(db, &view_schemas, table, joins)?;
+    //names may be qualified with their table, from here on every name is the one the
+    //schema shows
+    let schema = Schema::new(&tables);
+    schema.qualify(&mut columns, &mut conditions, &mut clauses)?;
+    let output = Output::new(&schema.attributes, &columns, &clauses, functions)?;
+    for condition in conditions.iter().filter(|c| !is_exists(c)) {
+        check_condition(condition, &schema.attributes, functions)?;
+    }
+    for condition in clauses.having.iter().filter(|c| !is_exists(c)) {
+        check_condition(condition, output.rows(), functions)?;
+    }
+    let header = output.header();
+
+    let decodedtable = read_table_info(data_file)?;
+    let stats = column_stats(
+        data_file,
+        &decodedtable,
+        &tables,
+        &view_rows,
+        &schema.attributes,
+    )?;
+    let steps = Steps {
+        conditions,
+        clauses,
+        output,
+        view_rows,
+    };
+    let plan = build_plan(&tables, joins, &schema, steps, &stats)?;
+
+    if explain {
+        let rows = plan.explain().into_iter().map(|line| {
+            Ok(Row {
+                cells: vec![Cell::new(line)],
+            })
+        });
+        return Ok((
+            vec![Attr::new("plan".to_string(), DataType::String)],
+            Box::new(rows),
+        ));
+    }
+    Ok((header, plan.run(data_file, &decodedtable, functions)?))
+}
+
+//an exists left among the conditions of a pick is one whose subquery found nothing
+fn is_exists(condition: &Condition) -> bool {
+    matches!(condition.test, Test::Exists(_))
+}
+
+//the views a pick reads, under their own names
+struct Views {
+    schemas: Vec<TableSchema>,
+    rows: HashMap<String, Vec<Row>>,
+}
+
+//a view is expanded by running its query, its rows then stand in for those of a table
+fn expand_views<'n>(
+    source: &mut Source,
+    names: impl Iterator<Item = &'n str>,
+) -> Result<Views, String> {
+    let db = source.db;
+    let mut view_schemas = Vec::new();
+    let mut view_rows: HashMap<String, Vec<Row>> = HashMap::new();
+    for name in names {
+        if let Some(view) = db.views.iter().find(|v| v.name == name)
+            && !view_rows.contains_key(name)
+        {
+            let (attributes, rows) = run_view(source, view)?;
+            let rows: Vec<Row> = rows.collect::<Result<_, _>>()?;
+            view_schemas.push(TableSchema {
+                name: name.to_string(),
+                attributes,
+                last_id: 0,
+                rows: rows.len(),
+            });
+            view_rows.insert(name.to_string(), rows);
+        }
+    }
+    Ok(Views {
+        schemas: view_schemas,
+        rows: view_rows,
+    })
+}
+
+//the schemas of the picked table and of every joined one, in the order they are read
+fn picked_tables<'t>(
+    db: &'t Database,
+    view_schemas: &'t [TableSchema],
+    table: &str,
+    joins: &[Join],
+) -> Result<Vec<&'t TableSchema>, String> {
+    let find = |name: &str| {
+        db.table_details
+            .iter()
+            .chain(view_schemas)
+            .find(|t| t.name == name)
+    };
+    let Some(table_picked) = find(table) else {
+        return Err("Table not found".to_string());
+    };
+    let mut tables = vec![table_picked];
+    for join in joins {
+        let Some(joined) = find(&join.table) else {
+            return Err(format!("Table {} not found", join.table));
+        };
+        if tables.iter().any(|t| t.name == join.table) {
+            return Err(format!("Table {} is joined more than once", join.table));
+        }
+        tables.push(joined);
+    }
+    Ok(tables)
+}
+
+//what the pick list makes of the matching rows: the grouping folding them into one row
+//per group, when there is one, the expressions computed behind every row and the columns
+//then shown
+struct Output {
+    grouping: Option<Grouping>,
+    computed: Vec<(String, Expr)>,
+    extended: Vec<Attr>, //the columns of a row once its expressions are computed
+    indexes: Vec<usize>, //the columns shown, in the order of the pick list
+}
+
+impl Output {
+    //aggregates, group by and having fold the rows, having, order by and the pick list
+    //then refer to the columns of those rows
+    fn new(
+        attributes: &[Attr],
+        columns: &[Selective],
+        clauses: &Clauses,
+        functions: &Registry,
+    ) -> Result<Self, String> {
+        let grouped = !clauses.group_by.is_empty()
+            || !clauses.having.is_empty()
+            || columns.iter().any(|c| match c {
+                Selective::Aggregate { .. } => true,
+                Selective::Expr { expr, .. } => expr_columns(expr)
+                    .iter()
+                    .any(|name| parse_aggregate(name).is_some()),
+                Selective::Column(_) => false,
+            });
+        let (grouping, picked) = if grouped {
+            let (grouping, picked) = group_columns(attributes, columns, clauses)?;
+            (Some(grouping), picked)
+        } else {
+            (None, project(attributes, columns)?)
+        };
+        let rows = grouping.as_ref().map_or(attributes, |g| &g.attributes);
+
+        let mut output = Output {
+            extended: rows
+                .iter()
+                .map(|a| Attr::new(a.col_name.clone(), a.datatype))
+                .collect(),
+            grouping: None,
+            computed: Vec::new(),
+            indexes: Vec::new(),
+        };
+        for (name, expr) in picked {
+            match &expr {
+                Expr::Column(column) if column == &name => {
+                    let index = rows.iter().position(|a| &a.col_name == column).unwrap();
+                    output.indexes.push(index);
                 }
+                _ => {
+                    let datatype = datatype(&expr, rows, functions)?;
+                    output.extended.push(Attr::new(name.clone(), datatype));
+                    output.computed.push((name, expr));
+                    output.indexes.push(output.extended.len() - 1);
+                }
+            }
+        }
+        output.grouping = grouping;
+        Ok(output)
+    }
 
-                let mut indexes: Vec<usize> = Vec::new();
-                let mut cond: Vec<usize> = Vec::new();
+    //the columns of a row before its expressions are computed
+    fn rows(&self) -> &[Attr] {
+        &self.extended[..self.extended.len() - self.computed.len()]
+    }
 
-                if i == -1 {
-                    print!("Table not found")
-                } else {
-                    if let Some(table_picked) = alltables.get((i) as usize) {
-                        for (index, attribute) in table_picked.attributes.iter().enumerate() {
-                            if columns.contains(&attribute.col_name) {
-                                indexes.push(index);
-                            }
-                        }
-                    } else {
-                        println!("No table found at index {}", i);
-                    }
+    fn header(&self) -> Vec<Attr> {
+        self.indexes
+            .iter()
+            .map(|&col| {
+                Attr::new(
+                    self.extended[col].col_name.clone(),
+                    self.extended[col].datatype,
+                )
+            })
+            .collect()
+    }
+}
 
-                    if let Some(table_picked) = alltables.get((i) as usize) {
-                        for (index, attribute) in table_picked.attributes.iter().enumerate() {
-                            if att.contains(&attribute.col_name) {
-                                cond.push(index);
-                            }
-                        }
-                    } else {
-                        println!("No table found at index {}", i);
+//what a pick wants done with the rows of its tables, taken apart step by step while its
+//plan is built
+struct Steps {
+    conditions: Vec<Condition>,
+    clauses: Clauses,
+    output: Output,
+    view_rows: HashMap<String, Vec<Row>>,
+}
+
+//what the last analyze found for each column, under the name the schema shows
+fn column_stats(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    tables: &[&TableSchema],
+    view_rows: &HashMap<String, Vec<Row>>,
+    attributes: &[Attr],
+) -> Result<HashMap<String, ColumnStats>, String> {
+    let mut table_stats: Vec<Option<ColumnStats>> = Vec::new();
+    for t in tables {
+        let mut stats: Vec<Option<ColumnStats>> = match view_rows.contains_key(&t.name) {
+            true => Vec::new(),
+            false => read_stats(data_file, table_info, &t.name)?,
+        }
+        .into_iter()
+        .map(Some)
+        .collect();
+        stats.resize_with(t.attributes.len(), || None);
+        table_stats.extend(stats);
+    }
+    Ok(attributes
+        .iter()
+        .zip(table_stats)
+        .filter_map(|(attr, stats)| Some((attr.col_name.clone(), stats?)))
+        .collect())
+}
+
+//the logical plan lists the steps in the order they run, the physical plan then reads the
+//picked table through its key index when the where-clause names its keys. estimates use
+//the row counts of the catalog and the statistics of the columns
+fn build_plan(
+    tables: &[&TableSchema],
+    joins: &[Join],
+    schema: &Schema,
+    steps: Steps,
+    stats: &HashMap<String, ColumnStats>,
+) -> Result<Plan, String> {
+    let Steps {
+        conditions,
+        clauses,
+        output,
+        mut view_rows,
+    } = steps;
+    let attributes = &schema.attributes;
+    let stats = |name: &str| stats.get(name);
+    let mut leaf = |t: &TableSchema| match view_rows.remove(&t.name) {
+        Some(rows) => Plan::View {
+            view: t.name.clone(),
+            rows,
+        },
+        None => Plan::Scan {
+            table: t.name.clone(),
+            rows: t.rows,
+        },
+    };
+    let keys = tables[0]
+        .attributes
+        .iter()
+        .position(|a| a.key)
+        .and_then(|column| {
+            Some(Keys {
+                column,
+                datatype: attributes[column].datatype,
+                values: key_values(&conditions, &attributes[column], attributes)?,
+            })
+        });
+
+    let mut plan = leaf(tables[0]);
+    let mut start = tables[0].attributes.len();
+    for (join, joined) in joins.iter().zip(&tables[1..]) {
+        let width = joined.attributes.len();
+        plan = Plan::Join {
+            input: Box::new(plan),
+            joined: Box::new(leaf(joined)),
+            on: join
+                .on
+                .iter()
+                .map(|c| format!("{} {} {}", c.left, c.op, c.right))
+                .collect(),
+            join: JoinPlan::new(join, schema, start, width)?,
+        };
+        start += width;
+    }
+    plan = plan.filter(conditions, attributes, &stats);
+
+    let rows = output.rows().to_vec();
+    let header = output.header();
+    let Output {
+        grouping,
+        computed,
+        extended,
+        indexes,
+    } = output;
+    if let Some(grouping) = grouping {
+        plan = Plan::Aggregate {
+            input: Box::new(plan),
+            groups: clauses
+                .group_by
+                .iter()
+                .map(|name| stats(name).map(|s| s.distinct.max(1)))
+                .product(),
+            group_by: clauses.group_by,
+            grouping,
+        };
+        plan = plan.filter(clauses.having, &rows, &stats);
+    }
+    if !computed.is_empty() {
+        plan = Plan::Compute {
+            input: Box::new(plan),
+            columns: computed,
+            attributes: rows,
+        };
+    }
+    if !clauses.order_by.is_empty() {
+        plan = Plan::Sort {
+            input: Box::new(plan),
+            keys: sort_columns(&extended, &clauses.order_by)?,
+            names: clauses
+                .order_by
+                .iter()
+                .map(|k| format!("{}{}", k.column, if k.descending { " desc" } else { "" }))
+                .collect(),
+        };
+    }
+    plan = Plan::Project {
+        input: Box::new(plan),
+        columns: header,
+        indexes,
+        distinct: clauses.distinct,
+        ordered: !clauses.order_by.is_empty() || clauses.limit.is_some(),
+    };
+    if clauses.limit.is_some() || clauses.offset > 0 {
+        plan = Plan::Limit {
+            input: Box::new(plan),
+            limit: clauses.limit,
+            offset: clauses.offset,
+        };
+    }
+    Ok(plan.physical(keys))
+}
+
+//the rows meeting every condition, a condition that fails to evaluate gives its error
+fn keep_matching<'s>(
+    rows: RowStream<'s>,
+    attributes: Vec<Attr>,
+    conditions: Vec<Condition>,
+    functions: &'s Registry,
+) -> RowStream<'s> {
+    Box::new(rows.filter_map(move |row| {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e)),
+        };
+        match satisfies(&row, &attributes, &conditions, functions) {
+            Ok(true) => Some(Ok(row)),
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }))
+}
+
+//runs the subqueries of the conditions: `in (<pick>)` becomes an `in` list of the picked
+//values and an `exists` that found a row is dropped. one that found nothing is kept, the
+//plan then knows no row can match
+fn run_subqueries(
+    source: &mut Source,
+    conditions: Vec<Condition>,
+) -> Result<Vec<Condition>, String> {
+    let mut kept = Vec::new();
+    for Condition { left, test } in conditions {
+        match test {
+            Test::InPick(subquery) => {
+                let (header, rows) = run_pick(source.reborrow(), *subquery)?;
+                if header.len() != 1 {
+                    let left = left.map(|l| l.to_string()).unwrap_or_default();
+                    return Err(format!(
+                        "Subquery for {} must pick exactly one column",
+                        left
+                    ));
+                }
+                let mut values = Vec::new();
+                for row in rows {
+                    if let Some(cell) = row?.cells.pop().filter(|c| !c.null) {
+                        values.push(Expr::Literal(cell.value));
                     }
                 }
+                kept.push(Condition {
+                    left,
+                    test: Test::In(values),
+                });
+            }
+            //a single row is enough to know
+            Test::Exists(subquery) => {
+                let found = run_pick(source.reborrow(), (*subquery).clone())?
+                    .1
+                    .next()
+                    .transpose()?
+                    .is_some();
+                if !found {
+                    kept.push(Condition {
+                        left,
+                        test: Test::Exists(subquery),
+                    });
+                }
+            }
+            test => kept.push(Condition { left, test }),
+        }
+    }
+    Ok(kept)
+}
 
-                data_file.seek(SeekFrom::Start(PAGE_SIZE as u64)).unwrap();
-                let mut table_page = vec![0u8; PAGE_SIZE];
-                let table_bytes = data_file.read(&mut table_page).unwrap();
-                table_page.truncate(table_bytes);
-                let decodedtable: TableInfo = rmp_serde::from_slice(&table_page).unwrap();
-                let pointer = decodedtable.tables[&table];
-
-                let table_offset = (pointer as u64) * 4096;
-                data_file.seek(SeekFrom::Start(table_offset)).unwrap();
-                let mut rows_bytes = vec![0u8; PAGE_SIZE];
-                let n = data_file.read(&mut rows_bytes).unwrap();
-                rows_bytes.truncate(n);
-                let table_rows: TableRow = rmp_serde::from_slice(&rows_bytes).unwrap();
-
-                let mut table_rows_cond: Vec<Row> = Vec::new();
-
-                for element in &table_rows.rows {
-                    let mut satisfies_all = true;
-
-                    for (cond_idx, &col) in cond.iter().enumerate() {
-                        if let Some(c) = element.cells.get(col) {
-                            let op = &oper[cond_idx];
-                            let oper: Operation = match op.as_str() {
-                                "==" => Operation::Equal,
-                                ">" => Operation::Grt,
-                                "<" => Operation::Les,
-                                ">=" => Operation::GrtEq,
-                                "<=" => Operation::LesEq,
-                                "!=" => Operation::NotEq,
-                                _ => panic!("Unknown operation"),
-                            };
+//runs the saved query of a view
+fn run_view<'s>(
+    source: &'s mut Source,
+    view: &ViewSchema,
+) -> Result<(Vec<Attr>, RowStream<'s>), String> {
+    let mut parsed = MyParser::parse(Rule::view_query, &view.query)
+        .map_err(|_| format!("View {} does not parse", view.name))?;
+    let pick = parsed.next().unwrap().into_inner().next().unwrap();
+    run_pick(source.reborrow(), build_ast(pick))
+}
 
-                            let v = &val[cond_idx];
-                            if !operation(&c, oper, v.clone()) {
-                                satisfies_all = false;
-                                break;
-                            }
-                        }
-                    }
+fn build_assignments(pair: pest::iterators::Pair<Rule>) -> Vec<Assignment> {
+    let mut assignments = Vec::new();
+    for assign in pair.into_inner() {
+        if assign.as_rule() == Rule::assignment {
+            let mut parts = assign.into_inner().collect::<Vec<_>>();
+            let value = build_operand(parts.pop().unwrap());
+            let column = parts.pop().map(|p| p.as_str().to_string());
+            assignments.push(Assignment { column, value });
+        }
+    }
+    assignments
+}
 
-                    if satisfies_all {
-                        let mut currcells: Vec<Cell> = Vec::new();
-                        for &col in &indexes {
-                            if let Some(c) = element.cells.get(col) {
-                                currcells.push(Cell {
-                                    value: c.value.clone(),
-                                });
-                            }
-                        }
-                        table_rows_cond.push(Row { cells: currcells });
-                    }
+fn build_conditions(pair: pest::iterators::Pair<Rule>) -> Vec<Condition> {
+    let mut conditions = Vec::new();
+    for cond in pair.into_inner() {
+        if cond.as_rule() == Rule::cond {
+            let mut parts = cond.into_inner();
+            let first = parts.next().unwrap();
+            if first.as_rule() == Rule::exists {
+                let subquery = build_ast(first.into_inner().next().unwrap());
+                conditions.push(Condition {
+                    left: None,
+                    test: Test::Exists(Box::new(subquery)),
+                });
+                continue;
+            }
+            let left = build_expr(first);
+            let test = parts.next().unwrap();
+            let test = match test.as_rule() {
+                Rule::between => {
+                    let mut ends = test.into_inner().map(build_operand);
+                    Test::Between(ends.next().unwrap(), ends.next().unwrap())
+                }
+                Rule::in_list => Test::In(test.into_inner().map(build_operand).collect()),
+                Rule::in_pick => {
+                    Test::InPick(Box::new(build_ast(test.into_inner().next().unwrap())))
                 }
+                _ => Test::Compare(build_op(test), build_expr(parts.next().unwrap())),
+            };
+            conditions.push(Condition {
+                left: Some(left),
+                test,
+            });
+        }
+    }
+    conditions
+}
+
+//a literal value, without the quotes around a quoted one
+fn build_value(pair: pest::iterators::Pair<Rule>) -> String {
+    let value = pair.as_str();
+    match value.chars().next() {
+        Some(quote @ ('\'' | '"')) => value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+            .unwrap_or(value)
+            .to_string(),
+        _ => value.to_string(),
+    }
+}
+
+//a value or a placeholder, `?` only reaches here outside a prepared statement
+fn build_operand(pair: pest::iterators::Pair<Rule>) -> Expr {
+    match pair.as_rule() {
+        Rule::param => build_param(pair),
+        _ => Expr::Literal(build_value(pair)),
+    }
+}
+
+fn build_param(pair: pest::iterators::Pair<Rule>) -> Expr {
+    let index = pair.as_str()[1..].parse::<usize>().unwrap_or(1);
+    Expr::Param(index - 1)
+}
+
+fn build_op(pair: pest::iterators::Pair<Rule>) -> Operation {
+    match pair.as_str() {
+        "==" => Operation::Equal,
+        ">" => Operation::Grt,
+        "<" => Operation::Les,
+        ">=" => Operation::GrtEq,
+        "<=" => Operation::LesEq,
+        "!=" => Operation::NotEq,
+        "like" => Operation::Like,
+        "ilike" => Operation::ILike,
+        "contains" => Operation::Contains,
+        "starts with" => Operation::StartsWith,
+        "ends with" => Operation::EndsWith,
+        "matches" => Operation::Matches,
+        _ => panic!("Unknown operation"),
+    }
+}
+
+fn build_join(pair: pest::iterators::Pair<Rule>) -> Join {
+    let mut parts = pair.into_inner();
+    let left = parts.next().unwrap().as_str() == "left join";
+    let table = parts.next().unwrap().as_str().to_string();
+    let on = parts
+        .map(|test| {
+            let mut sides = test.into_inner();
+            JoinCondition {
+                left: sides.next().unwrap().as_str().to_string(),
+                op: build_op(sides.next().unwrap()),
+                right: sides.next().unwrap().as_str().to_string(),
+            }
+        })
+        .collect();
+    Join { table, left, on }
+}
 
-                for name in &columns {
-                    print!("{:<10}", name);
+fn build_selectives(pair: pest::iterators::Pair<Rule>) -> Vec<Selective> {
+    pair.into_inner()
+        .filter(|sel| sel.as_rule() == Rule::selective)
+        .map(|sel| {
+            let mut parts = sel.into_inner();
+            let first = parts.next().unwrap();
+            if first.as_rule() == Rule::star {
+                return Selective::Column("*".to_string());
+            }
+            let alias = parts.next().map(|a| a.into_inner().as_str().to_string());
+            match (build_expr(first), alias) {
+                (Expr::Column(name), None) => {
+                    parse_aggregate(&name).unwrap_or(Selective::Column(name))
                 }
-                println!();
-                println!("{}", "-".repeat(columns.len() * 10));
-                for element in &table_rows_cond {
-                    for c in &element.cells {
-                        print!("{:<10}", c.value);
+                (expr, alias) => Selective::Expr { expr, alias },
+            }
+        })
+        .collect()
+}
+
+//`+ -` bind looser than `* / %`, both group from the left
+fn build_expr(pair: pest::iterators::Pair<Rule>) -> Expr {
+    match pair.as_rule() {
+        Rule::expr | Rule::term => {
+            let mut parts = pair.into_inner();
+            let mut expr = build_expr(parts.next().unwrap());
+            while let Some(op) = parts.next() {
+                let op = match op.as_str() {
+                    "+" => ArithOp::Add,
+                    "-" => ArithOp::Sub,
+                    "*" => ArithOp::Mul,
+                    "/" => ArithOp::Div,
+                    _ => ArithOp::Rem,
+                };
+                expr = Expr::Binary {
+                    op,
+                    left: Box::new(expr),
+                    right: Box::new(build_expr(parts.next().unwrap())),
+                };
+            }
+            expr
+        }
+        Rule::aggregate => Expr::Column(build_aggregate(pair).to_string()),
+        Rule::call => {
+            let mut parts = pair.into_inner();
+            let name = parts.next().unwrap().as_str().to_lowercase();
+            let args = parts
+                .map(|argument| {
+                    let mut sides = argument.into_inner();
+                    let left = build_expr(sides.next().unwrap());
+                    match sides.next() {
+                        Some(op) => Expr::Compare {
+                            op: build_op(op),
+                            left: Box::new(left),
+                            right: Box::new(build_expr(sides.next().unwrap())),
+                        },
+                        None => left,
                     }
-                    println!();
+                })
+                .collect();
+            Expr::Call { name, args }
+        }
+        Rule::number => Expr::Literal(pair.as_str().to_string()),
+        Rule::string => Expr::Literal(build_value(pair)),
+        Rule::param => build_param(pair),
+        _ => Expr::Column(pair.as_str().to_string()),
+    }
+}
+
+fn build_aggregate(pair: pest::iterators::Pair<Rule>) -> Selective {
+    let mut parts = pair.into_inner();
+    let func = match parts.next().unwrap().as_str() {
+        "count" => AggregateFn::Count,
+        "sum" => AggregateFn::Sum,
+        "avg" => AggregateFn::Avg,
+        "min" => AggregateFn::Min,
+        _ => AggregateFn::Max,
+    };
+    let column = match parts.next().unwrap().as_str() {
+        "*" => None,
+        name => Some(name.to_string()),
+    };
+    Selective::Aggregate { func, column }
+}
+
+//a column name, or an aggregate written the way the pick list header shows it
+fn build_name(pair: pest::iterators::Pair<Rule>) -> String {
+    match pair.as_rule() {
+        Rule::aggregate => build_aggregate(pair).to_string(),
+        _ => pair.as_str().to_string(),
+    }
+}
+
+//the aggregate a having or order by name stands for
+fn parse_aggregate(name: &str) -> Option<Selective> {
+    let mut parsed = MyParser::parse(Rule::aggregate, name).ok()?;
+    let pair = parsed.next()?;
+    (pair.as_str() == name).then(|| build_aggregate(pair))
+}
+
+//the optional clauses trailing a pick
+fn build_clauses<'a>(pairs: impl Iterator<Item = pest::iterators::Pair<'a, Rule>>) -> Clauses {
+    let mut clauses = Clauses::default();
+    for clause in pairs {
+        match clause.as_rule() {
+            Rule::group_by => {
+                clauses.group_by = clause
+                    .into_inner()
+                    .map(|c| c.as_str().to_string())
+                    .collect()
+            }
+            Rule::having => clauses.having = build_conditions(clause.into_inner().next().unwrap()),
+            Rule::order_by => {
+                for key in clause.into_inner() {
+                    let mut parts = key.into_inner();
+                    let column = build_name(parts.next().unwrap());
+                    let descending = parts.next().is_some_and(|dir| dir.as_str() == "desc");
+                    clauses.order_by.push(SortKey { column, descending });
                 }
-            } else {
-                println!("No database is active.");
             }
+            Rule::limit => clauses.limit = Some(build_count(clause)),
+            Rule::offset => clauses.offset = build_count(clause),
+            _ => {}
         }
     }
+    clauses
+}
+
+fn build_count(pair: pest::iterators::Pair<Rule>) -> usize {
+    let count = pair.into_inner().next().unwrap().as_str();
+    count.parse().unwrap_or(usize::MAX)
+}
+
+fn build_foreign_key(pair: pest::iterators::Pair<Rule>) -> ForeignKey {
+    let mut parts = pair.into_inner();
+    let table = parts.next().unwrap().as_str().to_string();
+    let column = parts.next().unwrap().as_str().to_string();
+    let on_delete = match parts.next() {
+        Some(action) => match action.into_inner().next().unwrap().as_str() {
+            "cascade" => OnDelete::Cascade,
+            "set null" => OnDelete::SetNull,
+            _ => OnDelete::Restrict,
+        },
+        None => OnDelete::Restrict,
+    };
+    ForeignKey {
+        table,
+        column,
+        on_delete,
+    }
 }
 
 pub fn build_ast(pair: pest::iterators::Pair<Rule>) -> AstNode {
@@ -516,8 +1962,33 @@ pub fn build_ast(pair: pest::iterators::Pair<Rule>) -> AstNode {
                         if def.as_rule() == Rule::att_def {
                             let mut parts = def.into_inner();
                             let col_name = parts.next().unwrap().as_str().to_string();
-                            let col_type = parts.next().unwrap().as_str().to_string();
-                            cols.push((col_name, col_type));
+                            let col_type = parts.next().unwrap().as_str();
+                            let datatype = match col_type.to_lowercase().as_str() {
+                                "int" => DataType::Int,
+                                "string" => DataType::String,
+                                "bool" => DataType::Bool,
+                                _ => panic!("Unknown data type: {}", col_type),
+                            };
+                            let mut new_attr = Attr::new(col_name, datatype);
+                            for constraint in parts {
+                                match constraint.as_rule() {
+                                    Rule::references => {
+                                        new_attr.references = Some(build_foreign_key(constraint));
+                                    }
+                                    Rule::default_value => {
+                                        let value = constraint.into_inner().next().unwrap();
+                                        new_attr.default = Some(build_value(value));
+                                    }
+                                    Rule::autoincrement => new_attr.autoincrement = true,
+                                    Rule::key => new_attr.key = true,
+                                    Rule::check => {
+                                        let condition = constraint.into_inner().next().unwrap();
+                                        new_attr.check = Some(condition.as_str().to_string());
+                                    }
+                                    _ => {}
+                                }
+                            }
+                            cols.push(new_attr);
                         }
                     }
                 }
@@ -529,77 +2000,245 @@ pub fn build_ast(pair: pest::iterators::Pair<Rule>) -> AstNode {
             }
         }
 
-        Rule::add => {
+        Rule::analyze => AstNode::Analyze {
+            table: pair.into_inner().next().map(|t| t.as_str().to_string()),
+        },
+
+        Rule::explain => AstNode::Explain(Box::new(build_ast(pair.into_inner().next().unwrap()))),
+
+        Rule::make_view => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str().to_string();
+            let query = inner.next().unwrap().as_str().to_string();
+            AstNode::MakeView { name, query }
+        }
+
+        Rule::add | Rule::put => {
+            let put = pair.as_rule() == Rule::put;
             let mut inner_rules = pair.into_inner();
             let table = inner_rules.next().unwrap().as_str().to_string();
-            let assignments_pair = inner_rules.next().unwrap();
-
-            let mut assignments = Vec::new();
-            for assign in assignments_pair.into_inner() {
-                if assign.as_rule() == Rule::assignment {
-                    let mut parts = assign.into_inner();
-                    let value = parts.next().unwrap().as_str().to_string();
-                    assignments.push(value);
-                }
-            }
+            let values_pair = inner_rules.next().unwrap();
+
+            let rows = match values_pair.as_rule() {
+                Rule::tuples => values_pair
+                    .into_inner()
+                    .map(|tuple| build_assignments(tuple.into_inner().next().unwrap()))
+                    .collect(),
+                _ => vec![build_assignments(values_pair)],
+            };
+            let on_conflict = match inner_rules.next() {
+                Some(action) => match action.into_inner().next().unwrap().as_str() {
+                    "ignore" => Some(Conflict::Ignore),
+                    "update" => Some(Conflict::Update),
+                    _ => Some(Conflict::Replace),
+                },
+                None if put => Some(Conflict::Replace),
+                None => None,
+            };
             AstNode::Add {
                 table,
-                values: assignments,
+                rows,
+                on_conflict,
             }
         }
 
-        Rule::pick => {
-            let mut inner_pick = pair.into_inner();
-            let table = inner_pick.next().unwrap().as_str().to_string();
-            let selectives_pair = inner_pick.next().unwrap();
-            let mut picked = vec![];
-
-            for sel in selectives_pair.into_inner() {
-                if sel.as_rule() == Rule::selective {
-                    picked.push(sel.as_str().to_string());
-                }
-            }
-            AstNode::Pick {
-                table,
-                columns: picked,
-            }
+        Rule::remove => {
+            let mut inner = pair.into_inner();
+            let table = inner.next().unwrap().as_str().to_string();
+            let conditions = build_conditions(inner.next().unwrap());
+            AstNode::Remove { table, conditions }
         }
-        Rule::conditional_pick => {
-            let mut inner_cond = pair.into_inner();
-            let table = inner_cond.next().unwrap().as_str().to_string();
-            let selectives_pair = inner_cond.next().unwrap();
-            let mut picked = vec![];
 
-            for sel in selectives_pair.into_inner() {
-                if sel.as_rule() == Rule::selective {
-                    picked.push(sel.as_str().to_string());
-                }
-            }
-
-            let which_cond = inner_cond.next().unwrap();
-            let mut attri: Vec<String> = Vec::new();
-            let mut oper = Vec::new();
-            let mut values = Vec::new();
-
-            for cond in which_cond.into_inner() {
-                if cond.as_rule() == Rule::cond {
-                    let mut parts = cond.into_inner();
-                    let attr = parts.next().unwrap().as_str().to_string();
-                    let op = parts.next().unwrap().as_str().to_string();
-                    let val = parts.next().unwrap().as_str().to_string();
-                    attri.push(attr);
-                    oper.push(op);
-                    values.push(val);
-                }
+        Rule::pick | Rule::conditional_pick => {
+            let mut inner_pick = pair.into_inner().peekable();
+            let table = inner_pick.next().unwrap().as_str().to_string();
+            let mut joins = Vec::new();
+            while let Some(join) = inner_pick.next_if(|p| p.as_rule() == Rule::join) {
+                joins.push(build_join(join));
             }
-            AstNode::ConditionalPick {
-                table,
-                columns: picked,
-                att: attri,
-                oper: oper,
-                val: values,
+            let selectives = inner_pick.next().unwrap();
+            let distinct = selectives
+                .clone()
+                .into_inner()
+                .next()
+                .is_some_and(|p| p.as_rule() == Rule::distinct);
+            let picked = build_selectives(selectives);
+
+            let which_cond = inner_pick.next_if(|p| p.as_rule() == Rule::condition);
+            let clauses = Clauses {
+                distinct,
+                ..build_clauses(inner_pick)
+            };
+            match which_cond {
+                Some(which_cond) => AstNode::ConditionalPick {
+                    table,
+                    joins,
+                    columns: picked,
+                    conditions: build_conditions(which_cond),
+                    clauses,
+                },
+                None => AstNode::Pick {
+                    table,
+                    joins,
+                    columns: picked,
+                    clauses,
+                },
             }
         }
         _ => unimplemented!(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_input;
+
+    fn schema() -> TableSchema {
+        let mut id = Attr::new("id".to_string(), DataType::Int);
+        id.key = true;
+        id.autoincrement = true;
+        let mut name = Attr::new("name".to_string(), DataType::String);
+        name.default = Some("anon".to_string());
+        TableSchema {
+            name: "people".to_string(),
+            attributes: vec![id, name, Attr::new("age".to_string(), DataType::Int)],
+            last_id: 0,
+            rows: 0,
+        }
+    }
+
+    fn positional(values: &[&str]) -> Vec<Assignment> {
+        values
+            .iter()
+            .map(|v| Assignment {
+                column: None,
+                value: Expr::Literal(v.to_string()),
+            })
+            .collect()
+    }
+
+    fn named(values: &[(&str, &str)]) -> Vec<Assignment> {
+        values
+            .iter()
+            .map(|(c, v)| Assignment {
+                column: Some(c.to_string()),
+                value: Expr::Literal(v.to_string()),
+            })
+            .collect()
+    }
+
+    fn values(row: &Row) -> Vec<Option<&str>> {
+        row.cells
+            .iter()
+            .map(|c| (!c.null).then_some(c.value.as_str()))
+            .collect()
+    }
+
+    fn statement(input: &str) -> AstNode {
+        build_ast(parse_input(input).unwrap().into_inner().next().unwrap())
+    }
+
+    #[test]
+    fn short_positional_rows_generate_the_id() {
+        let (row, last_id) = build_row(positional(&["ann", "30"]), &schema(), 4).unwrap();
+        assert_eq!(values(&row), [Some("5"), Some("ann"), Some("30")]);
+        assert_eq!(last_id, 5);
+
+        let (row, last_id) = build_row(positional(&["9", "bob", "null"]), &schema(), 4).unwrap();
+        assert_eq!(values(&row), [Some("9"), Some("bob"), None]);
+        assert_eq!(last_id, 9);
+    }
+
+    #[test]
+    fn named_values_fill_in_any_order() {
+        let (row, last_id) = build_row(named(&[("age", "3")]), &schema(), 0).unwrap();
+        assert_eq!(values(&row), [Some("1"), Some("anon"), Some("3")]);
+        assert_eq!(last_id, 1);
+
+        let (row, last_id) =
+            build_row(named(&[("name", "cy"), ("id", "12")]), &schema(), 2).unwrap();
+        assert_eq!(values(&row), [Some("12"), Some("cy"), None]);
+        assert_eq!(last_id, 12);
+    }
+
+    #[test]
+    fn bad_assignments_are_errors() {
+        let mut mixed = named(&[("age", "3")]);
+        mixed.extend(positional(&["ann"]));
+        let error = build_row(mixed, &schema(), 0).unwrap_err();
+        assert_eq!(error, "Cannot mix named and positional values");
+        assert!(build_row(named(&[("height", "3")]), &schema(), 0).is_err());
+        assert!(build_row(named(&[("age", "3"), ("age", "4")]), &schema(), 0).is_err());
+    }
+
+    #[test]
+    fn only_a_literal_null_is_null() {
+        let age = Attr::new("age".to_string(), DataType::Int);
+        let name = Attr::new("name".to_string(), DataType::String);
+        let null = Expr::Literal("null".to_string());
+        assert!(literal_cell(null, Some(&name)).unwrap().null);
+        let text = Expr::Value(Value::Text("null".to_string()));
+        let cell = literal_cell(text.clone(), Some(&name)).unwrap();
+        assert!(!cell.null && cell.value == "null");
+        assert!(literal_cell(text, Some(&age)).is_err());
+        assert!(
+            literal_cell(Expr::Value(Value::Null), Some(&age))
+                .unwrap()
+                .null
+        );
+    }
+
+    #[test]
+    fn comparisons_follow_the_column_type() {
+        let cell = Cell::new("007".to_string());
+        let compare = |datatype, op| operation(&cell, datatype, op, "7".to_string());
+        assert!(compare(DataType::Int, Operation::Equal));
+        assert!(!compare(DataType::String, Operation::Equal));
+        assert!(compare(DataType::String, Operation::Les));
+        let ten = Cell::new("10".to_string());
+        assert!(operation(
+            &ten,
+            DataType::String,
+            Operation::Les,
+            "9".to_string()
+        ));
+        assert!(!operation(
+            &ten,
+            DataType::Int,
+            Operation::Les,
+            "9".to_string()
+        ));
+        assert!(!operation(
+            &Cell::null(),
+            DataType::Int,
+            Operation::NotEq,
+            "1".to_string()
+        ));
+        let name = Cell::new("Hello".to_string());
+        assert!(operation(
+            &name,
+            DataType::String,
+            Operation::ILike,
+            "h%".to_string()
+        ));
+    }
+
+    #[test]
+    fn negative_numbers_are_values() {
+        let AstNode::Add { rows, .. } = statement("t.add( amount = -5, -1.5 )") else {
+            panic!("not an add");
+        };
+        let literals: Vec<_> = rows[0]
+            .iter()
+            .map(|a| match &a.value {
+                Expr::Literal(value) => value.as_str(),
+                _ => panic!("not a literal"),
+            })
+            .collect();
+        assert_eq!(literals, ["-5", "-1.5"]);
+        assert!(parse_input("make table t( a : int default -1 )").is_ok());
+        assert!(parse_input("t.pick( a ) where ( a in (-1, 2), a between -5 and 5 )").is_ok());
+        assert!(parse_input("t.add( a = -b )").is_err());
+    }
+}
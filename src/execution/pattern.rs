@@ -0,0 +1,97 @@
+use crate::structures::Operation;
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    //patterns are compiled once per query instead of once per row
+    static COMPILED: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+//the result of a pattern operation, `None` when `op` compares instead
+pub fn pattern_match(value: &str, op: Operation, pattern: &str) -> Option<bool> {
+    match op {
+        Operation::Like => Some(like(value, pattern)),
+        Operation::ILike => Some(like(&value.to_lowercase(), &pattern.to_lowercase())),
+        Operation::Contains => Some(value.contains(pattern)),
+        Operation::StartsWith => Some(value.starts_with(pattern)),
+        Operation::EndsWith => Some(value.ends_with(pattern)),
+        Operation::Matches => Some(COMPILED.with(|compiled| {
+            let mut compiled = compiled.borrow_mut();
+            if !compiled.contains_key(pattern) {
+                match Regex::new(pattern) {
+                    Ok(regex) => compiled.insert(pattern.to_string(), regex),
+                    Err(_) => return false,
+                };
+            }
+            compiled[pattern].is_match(value)
+        })),
+        _ => None,
+    }
+}
+
+//an error for a `matches` pattern that is not a valid regular expression
+pub fn check_pattern(op: Operation, pattern: &str) -> Result<(), String> {
+    match op {
+        Operation::Matches => Regex::new(pattern)
+            .map(|_| ())
+            .map_err(|e| format!("Invalid regular expression {}: {}", pattern, e)),
+        _ => Ok(()),
+    }
+}
+
+//`%` stands for any run of characters and `_` for exactly one. a mismatch after a `%`
+//retries with the `%` taking one more character
+fn like(value: &str, pattern: &str) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut v, mut p) = (0, 0);
+    let mut retry: Option<(usize, usize)> = None;
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '_' || pattern[p] == value[v]) {
+            v += 1;
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == '%' {
+            retry = Some((p, v));
+            p += 1;
+        } else if let Some((percent, start)) = retry {
+            retry = Some((percent, start + 1));
+            p = percent + 1;
+            v = start + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '%')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn like_matches_runs_and_single_characters() {
+        assert!(like("hello", "h%o"));
+        assert!(like("hello", "h_llo"));
+        assert!(like("hello", "%"));
+        assert!(like("", "%%"));
+        assert!(!like("hello", "h_o"));
+        assert!(!like("", "_"));
+    }
+
+    #[test]
+    fn like_retries_a_percent_after_a_mismatch() {
+        assert!(like("abcabd", "%abd"));
+        assert!(like("aXbXbc", "a%b%c"));
+        assert!(!like("abcab", "%abd"));
+        assert!(!like("abc", "%b"));
+    }
+
+    #[test]
+    fn ilike_ignores_case_and_comparisons_are_left_alone() {
+        assert_eq!(pattern_match("Hello", Operation::ILike, "h%O"), Some(true));
+        assert_eq!(pattern_match("Hello", Operation::Like, "h%O"), Some(false));
+        assert_eq!(pattern_match("Hello", Operation::Equal, "Hello"), None);
+        assert!(check_pattern(Operation::Matches, "(").is_err());
+    }
+}
@@ -0,0 +1,532 @@
+use super::aggregate::Grouping;
+use super::join::JoinPlan;
+use super::sort::{SortColumn, sort_rows};
+use super::{Registry, RowStream, decimal, distinct_rows, evaluate, keep_matching, lookup_keys};
+use crate::storage::TableScan;
+use crate::structures::{
+    Attr, Cell, ColumnStats, Condition, DataType, Expr, Operation, Row, TableInfo, Test, Value,
+};
+use std::fs::File;
+
+//the steps of a pick, each reading the rows of the steps inside it. every step holds what
+//it needs to run, so the rows a pick gives are always the ones `explain` describes. the
+//logical plan only scans tables, `physical` then decides how the first table is read
+pub enum Plan {
+    Scan {
+        table: String,
+        rows: usize,
+    },
+    KeyLookup {
+        table: String,
+        keys: Keys,
+    },
+    View {
+        view: String,
+        rows: Vec<Row>, //a view's query already ran when the pick was planned
+    },
+    Join {
+        input: Box<Plan>,
+        joined: Box<Plan>,
+        on: Vec<String>,
+        join: JoinPlan,
+    },
+    Filter {
+        input: Box<Plan>,
+        conditions: Vec<Condition>,
+        attributes: Vec<Attr>,
+        selectivity: f64,
+    },
+    Aggregate {
+        input: Box<Plan>,
+        group_by: Vec<String>,
+        groups: Option<usize>, //distinct values of the group columns, when they were analyzed
+        grouping: Grouping,
+    },
+    //expressions of the pick list are computed into extra columns behind the row, where
+    //order by can find them by their alias
+    Compute {
+        input: Box<Plan>,
+        columns: Vec<(String, Expr)>,
+        attributes: Vec<Attr>,
+    },
+    Sort {
+        input: Box<Plan>,
+        keys: Vec<SortColumn>,
+        names: Vec<String>,
+    },
+    Project {
+        input: Box<Plan>,
+        columns: Vec<Attr>,
+        indexes: Vec<usize>,
+        distinct: bool,
+        ordered: bool, //rows come sorted, or only the first few are wanted
+    },
+    Limit {
+        input: Box<Plan>,
+        limit: Option<usize>,
+        offset: usize,
+    },
+}
+
+//the key column of the first table and the keys the where-clause pins it to
+pub struct Keys {
+    pub column: usize,
+    pub datatype: DataType,
+    pub values: Vec<String>,
+}
+
+impl Plan {
+    //keeps the rows meeting every condition, no step when there are none. `stats` gives
+    //what analyze found for a column
+    pub fn filter<'s>(
+        self,
+        conditions: Vec<Condition>,
+        attributes: &[Attr],
+        stats: &impl Fn(&str) -> Option<&'s ColumnStats>,
+    ) -> Plan {
+        if conditions.is_empty() {
+            return self;
+        }
+        Plan::Filter {
+            input: Box::new(self),
+            selectivity: conditions.iter().map(|c| selectivity(c, stats)).product(),
+            conditions,
+            attributes: attributes.to_vec(),
+        }
+    }
+
+    //reads the first table through its key index when the where-clause gave its keys, the
+    //filter above still checks every row that is found. a list naming more keys than the
+    //table has rows is read faster by scanning the table
+    pub fn physical(mut self, keys: Option<Keys>) -> Plan {
+        let source = self.source_mut();
+        if let (Plan::Scan { table, rows }, Some(keys)) = (&*source, keys)
+            && keys.values.len() <= (*rows).max(1)
+        {
+            *source = Plan::KeyLookup {
+                table: table.clone(),
+                keys,
+            };
+        }
+        self
+    }
+
+    fn source_mut(&mut self) -> &mut Plan {
+        match self {
+            Plan::Join { input, .. }
+            | Plan::Filter { input, .. }
+            | Plan::Aggregate { input, .. }
+            | Plan::Compute { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Project { input, .. }
+            | Plan::Limit { input, .. } => input.source_mut(),
+            leaf => leaf,
+        }
+    }
+
+    fn inputs(&self) -> Vec<&Plan> {
+        match self {
+            Plan::Scan { .. } | Plan::KeyLookup { .. } | Plan::View { .. } => Vec::new(),
+            Plan::Join { input, joined, .. } => vec![input, joined],
+            Plan::Filter { input, .. }
+            | Plan::Aggregate { input, .. }
+            | Plan::Compute { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Project { input, .. }
+            | Plan::Limit { input, .. } => vec![input],
+        }
+    }
+
+    //pulls rows through the steps a page at a time, nothing is read before the caller asks
+    //for the first row. without a sort or an aggregate below it, a limit stops the scan as
+    //soon as enough rows came through. every step owns what it reads, so the rows can be
+    //pulled after the plan is gone
+    pub fn run<'s>(
+        self,
+        data_file: &'s mut File,
+        table_info: &TableInfo,
+        functions: &'s Registry,
+    ) -> Result<RowStream<'s>, String> {
+        Ok(match self {
+            Plan::Scan { table, .. } => Box::new(TableScan::new(data_file, table_info, &table)),
+            Plan::KeyLookup { table, keys } => {
+                let rows = lookup_keys(
+                    data_file,
+                    table_info,
+                    &table,
+                    keys.column,
+                    keys.datatype,
+                    &keys.values,
+                )?;
+                Box::new(rows.into_iter().map(Ok))
+            }
+            Plan::View { rows, .. } => Box::new(rows.into_iter().map(Ok)),
+            //the joined side is read whole before the first incoming row
+            Plan::Join {
+                input,
+                joined,
+                join,
+                ..
+            } => {
+                let joined = joined
+                    .run(&mut *data_file, table_info, functions)?
+                    .collect::<Result<_, _>>()?;
+                join.run(input.run(data_file, table_info, functions)?, joined)
+            }
+            //an exists still among the conditions is one whose subquery found no rows, so
+            //nothing can match and nothing is read
+            Plan::Filter { conditions, .. }
+                if conditions.iter().any(|c| matches!(c.test, Test::Exists(_))) =>
+            {
+                Box::new(std::iter::empty())
+            }
+            Plan::Filter {
+                input,
+                conditions,
+                attributes,
+                ..
+            } => keep_matching(
+                input.run(data_file, table_info, functions)?,
+                attributes,
+                conditions,
+                functions,
+            ),
+            Plan::Aggregate {
+                input, grouping, ..
+            } => {
+                let groups = grouping.run(input.run(data_file, table_info, functions)?)?;
+                Box::new(groups.into_iter().map(Ok))
+            }
+            Plan::Compute {
+                input,
+                columns,
+                attributes,
+            } => Box::new(
+                input
+                    .run(data_file, table_info, functions)?
+                    .map(move |row| {
+                        let mut row = row?;
+                        let cells: Vec<Cell> = columns
+                            .iter()
+                            .map(|(_, expr)| {
+                                evaluate(expr, &row, &attributes, functions)
+                                    .unwrap_or_else(|_| Cell::null())
+                            })
+                            .collect();
+                        row.cells.extend(cells);
+                        Ok(row)
+                    }),
+            ),
+            //rows are ordered on all their columns before the projection drops any
+            Plan::Sort { input, keys, .. } => Box::new(sort_rows(
+                input.run(data_file, table_info, functions)?,
+                keys,
+            )?),
+            Plan::Project {
+                input,
+                columns,
+                indexes,
+                distinct,
+                ordered,
+            } => {
+                let projected = input
+                    .run(data_file, table_info, functions)?
+                    .map(move |row| {
+                        let row = row?;
+                        Ok(Row {
+                            cells: indexes.iter().map(|&col| row.cells[col].clone()).collect(),
+                        })
+                    });
+                match distinct {
+                    true => {
+                        let datatypes = columns.iter().map(|a| a.datatype).collect();
+                        distinct_rows(projected, datatypes, ordered)?
+                    }
+                    false => Box::new(projected),
+                }
+            }
+            Plan::Limit {
+                input,
+                limit,
+                offset,
+            } => Box::new(
+                input
+                    .run(data_file, table_info, functions)?
+                    .skip(offset)
+                    .take(limit.unwrap_or(usize::MAX)),
+            ),
+        })
+    }
+
+    //the rows a step is expected to give
+    pub fn estimate(&self) -> usize {
+        match self {
+            Plan::Scan { rows, .. } => *rows,
+            Plan::KeyLookup { keys, .. } => keys.values.len(),
+            Plan::View { rows, .. } => rows.len(),
+            //an equality join is taken to match each row of the bigger side about once
+            Plan::Join {
+                input,
+                joined,
+                join,
+                ..
+            } => {
+                let (l, r) = (input.estimate(), joined.estimate());
+                let rows = if join.hashed() { l.max(r) } else { l * r / 3 };
+                if join.left { rows.max(l) } else { rows }
+            }
+            Plan::Filter {
+                input, selectivity, ..
+            } => (input.estimate() as f64 * selectivity).ceil() as usize,
+            Plan::Aggregate { group_by, .. } if group_by.is_empty() => 1,
+            Plan::Aggregate { input, groups, .. } => {
+                let rows = input.estimate();
+                groups.unwrap_or(rows / 10).clamp(rows.min(1), rows)
+            }
+            Plan::Compute { input, .. }
+            | Plan::Sort { input, .. }
+            | Plan::Project { input, .. } => input.estimate(),
+            Plan::Limit {
+                input,
+                limit,
+                offset,
+            } => input
+                .estimate()
+                .saturating_sub(*offset)
+                .min(limit.unwrap_or(usize::MAX)),
+        }
+    }
+
+    //one line per step with its estimated rows, the steps it reads indented below it
+    pub fn explain(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.explain_into(0, &mut lines);
+        lines
+    }
+
+    fn explain_into(&self, depth: usize, lines: &mut Vec<String>) {
+        let list = |items: Vec<String>| items.join(", ");
+        let step = match self {
+            Plan::Scan { table, .. } => format!("Scan {}", table),
+            Plan::KeyLookup { table, keys } => {
+                format!("Key lookup {} ({})", table, keys.values.join(", "))
+            }
+            Plan::View { view, .. } => format!("View {}", view),
+            Plan::Join { on, join, .. } => format!(
+                "{} {}on {}",
+                if join.left { "Left join" } else { "Join" },
+                if join.hashed() {
+                    "hash "
+                } else {
+                    "nested loop "
+                },
+                on.join(", ")
+            ),
+            Plan::Filter { conditions, .. } => format!(
+                "Filter {}",
+                list(conditions.iter().map(|c| c.to_string()).collect())
+            ),
+            Plan::Aggregate { group_by, .. } if group_by.is_empty() => "Aggregate".to_string(),
+            Plan::Aggregate { group_by, .. } => format!("Aggregate by {}", group_by.join(", ")),
+            Plan::Compute { columns, .. } => format!(
+                "Compute {}",
+                list(
+                    columns
+                        .iter()
+                        .map(|(name, expr)| format!("{} as {}", expr, name))
+                        .collect()
+                )
+            ),
+            Plan::Sort { names, .. } => format!("Sort {}", names.join(", ")),
+            Plan::Project {
+                columns, distinct, ..
+            } => format!(
+                "Project {}{}",
+                if *distinct { "distinct " } else { "" },
+                list(columns.iter().map(|a| a.col_name.clone()).collect())
+            ),
+            Plan::Limit { limit, offset, .. } => match limit {
+                Some(limit) => format!("Limit {} offset {}", limit, offset),
+                None => format!("Offset {}", offset),
+            },
+        };
+        lines.push(format!(
+            "{}{}  (~{} rows)",
+            "  ".repeat(depth),
+            step,
+            self.estimate()
+        ));
+        for input in self.inputs() {
+            input.explain_into(depth + 1, lines);
+        }
+    }
+}
+
+//the share of rows a condition is expected to keep. with statistics an equality keeps one
+//distinct value and a range the part of the column's span it covers
+fn selectivity<'s>(condition: &Condition, stats: &impl Fn(&str) -> Option<&'s ColumnStats>) -> f64 {
+    let column = match &condition.left {
+        Some(Expr::Column(name)) => stats(name).filter(|s| s.distinct > 0),
+        _ => None,
+    };
+    let Some(column) = column else {
+        return default_selectivity(condition);
+    };
+    let distinct = column.distinct as f64;
+    let span = match &condition.test {
+        Test::Compare(Operation::Equal, _) => Some(1.0 / distinct),
+        Test::Compare(Operation::NotEq, _) => Some(1.0 - 1.0 / distinct),
+        Test::In(values) => Some((values.len() as f64 / distinct).min(1.0)),
+        Test::Compare(Operation::Les | Operation::LesEq, value) => position(column, value),
+        Test::Compare(Operation::Grt | Operation::GrtEq, value) => {
+            position(column, value).map(|below| 1.0 - below)
+        }
+        Test::Between(low, high) => position(column, low)
+            .zip(position(column, high))
+            .map(|(low, high)| (high - low).max(0.0)),
+        _ => None,
+    };
+    span.unwrap_or_else(|| default_selectivity(condition))
+}
+
+//the value a literal or a bound placeholder stands for, None for anything computed
+fn literal(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Literal(value) => Some(value.clone()),
+        Expr::Value(value) if *value != Value::Null => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+//where a value lies between the smallest and the largest number of a column, from 0 to 1
+fn position(stats: &ColumnStats, value: &Expr) -> Option<f64> {
+    let min = decimal(stats.min.as_deref()?)?;
+    let max = decimal(stats.max.as_deref()?)?;
+    let value = decimal(&literal(value)?)?;
+    (max > min).then(|| ((value - min) / (max - min)).clamp(0.0, 1.0))
+}
+
+//without statistics every kind of test is taken to keep a fixed share of the rows
+fn default_selectivity(condition: &Condition) -> f64 {
+    match &condition.test {
+        Test::Compare(Operation::Equal, _) => 0.1,
+        Test::Compare(Operation::NotEq, _) => 0.9,
+        Test::Compare(Operation::Grt | Operation::GrtEq | Operation::Les | Operation::LesEq, _) => {
+            1.0 / 3.0
+        }
+        Test::Compare(..) => 0.25,
+        Test::In(values) => (0.1 * values.len() as f64).min(1.0),
+        Test::Between(..) => 0.25,
+        Test::InPick(_) => 1.0,
+        //an exists only reaches the plan when its subquery found nothing
+        Test::Exists(_) => 0.0,
+    }
+}
+
+//the values a where-clause pins the key column to with `key == value` or `key in (...)`.
+//for an int key a value only qualifies when it is a whole number, the way the index
+//stores it
+pub fn key_values(
+    conditions: &[Condition],
+    key: &Attr,
+    attributes: &[Attr],
+) -> Option<Vec<String>> {
+    conditions.iter().find_map(|condition| {
+        let Some(Expr::Column(name)) = &condition.left else {
+            return None;
+        };
+        if name != &key.col_name {
+            return None;
+        }
+        let values = match &condition.test {
+            Test::Compare(Operation::Equal, value @ (Expr::Literal(_) | Expr::Value(_))) => {
+                vec![literal(value)?]
+            }
+            Test::Compare(Operation::Equal, Expr::Column(value))
+                if !attributes.iter().any(|a| &a.col_name == value) =>
+            {
+                vec![value.clone()]
+            }
+            Test::In(values) => values.iter().map(literal).collect::<Option<Vec<_>>>()?,
+            _ => return None,
+        };
+        values
+            .iter()
+            .all(|value| key.datatype != DataType::Int || value.parse::<i32>().is_ok())
+            .then_some(values)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(column: &str, test: Test) -> Condition {
+        Condition {
+            left: Some(Expr::Column(column.to_string())),
+            test,
+        }
+    }
+
+    fn literal(value: &str) -> Expr {
+        Expr::Literal(value.to_string())
+    }
+
+    #[test]
+    fn key_values_only_pins_whole_numbers_for_an_int_key() {
+        let mut id = Attr::new("id".to_string(), DataType::Int);
+        id.key = true;
+        let attributes = [id.clone(), Attr::new("name".to_string(), DataType::String)];
+        let equal = |value| [condition("id", Test::Compare(Operation::Equal, value))];
+        assert_eq!(
+            key_values(&equal(literal("7")), &id, &attributes),
+            Some(vec!["7".to_string()])
+        );
+        assert_eq!(key_values(&equal(literal("x")), &id, &attributes), None);
+        assert_eq!(key_values(&equal(literal("7.5")), &id, &attributes), None);
+        assert_eq!(
+            key_values(&equal(Expr::Value(Value::Null)), &id, &attributes),
+            None
+        );
+        assert_eq!(
+            key_values(&equal(Expr::Column("name".to_string())), &id, &attributes),
+            None
+        );
+        let within = [condition("id", Test::In(vec![literal("1"), literal("2")]))];
+        assert_eq!(
+            key_values(&within, &id, &attributes),
+            Some(vec!["1".to_string(), "2".to_string()])
+        );
+
+        let code = Attr::new("code".to_string(), DataType::String);
+        let equal = [condition(
+            "code",
+            Test::Compare(Operation::Equal, literal("x")),
+        )];
+        assert_eq!(
+            key_values(&equal, &code, std::slice::from_ref(&code)),
+            Some(vec!["x".to_string()])
+        );
+    }
+
+    #[test]
+    fn selectivity_reads_the_column_statistics() {
+        let stats = ColumnStats {
+            distinct: 50,
+            min: Some("0".to_string()),
+            max: Some("100".to_string()),
+        };
+        let lookup = |name: &str| (name == "n").then_some(&stats);
+        let of = |test| selectivity(&condition("n", test), &lookup);
+        assert_eq!(of(Test::Compare(Operation::Equal, literal("3"))), 0.02);
+        assert_eq!(of(Test::Compare(Operation::Les, literal("25"))), 0.25);
+        assert_eq!(of(Test::Compare(Operation::GrtEq, literal("25"))), 0.75);
+        assert_eq!(of(Test::Compare(Operation::Grt, literal("500"))), 0.0);
+        assert_eq!(of(Test::Between(literal("10"), literal("60"))), 0.5);
+        //a bound that is not a number falls back to the fixed share
+        assert_eq!(of(Test::Compare(Operation::Les, literal("x"))), 1.0 / 3.0);
+
+        let unknown = condition("m", Test::Compare(Operation::Equal, literal("3")));
+        assert_eq!(selectivity(&unknown, &lookup), 0.1);
+    }
+}
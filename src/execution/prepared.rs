@@ -0,0 +1,203 @@
+use super::build_ast;
+use crate::connection::{Connection, Rows};
+use crate::parser::{Rule, parse_input};
+use crate::structures::{AstNode, Condition, Expr, QueryResult, Selective, Test, Value};
+
+//a statement parsed once, with `$n` placeholders where the values of every bind go
+#[derive(Debug)]
+pub struct Prepared {
+    template: AstNode,
+    params: usize,
+}
+
+//parses a statement with `?` or `$1`, `$2`, ... placeholders. `?` are numbered in the order
+//they are written, `$n` may repeat a value. placeholders are only allowed where a statement
+//keeps its values, not in the stored text of a check or a view
+pub fn prepare(statement: &str) -> Result<Prepared, String> {
+    let statement = number_placeholders(statement)?;
+    let parsed = parse_input(&statement)?;
+    let written = parsed
+        .clone()
+        .into_inner()
+        .flatten()
+        .filter(|p| p.as_rule() == Rule::param)
+        .count();
+    let mut template = build_ast(parsed.into_inner().next().unwrap());
+
+    let mut found = Vec::new();
+    each_param(&mut template, &mut |param| {
+        if let Expr::Param(index) = param {
+            found.push(*index);
+        }
+    });
+    if found.len() != written {
+        return Err("Placeholders are only allowed in add, put, remove and picks".to_string());
+    }
+    let params = found.iter().max().map_or(0, |max| max + 1);
+    if let Some(missing) = (0..params).find(|i| !found.contains(i)) {
+        return Err(format!("Placeholder ${} is never used", missing + 1));
+    }
+    Ok(Prepared { template, params })
+}
+
+//writes every `?` as the `$n` it stands for
+fn number_placeholders(statement: &str) -> Result<String, String> {
+    let parsed = parse_input(statement)?;
+    let params: Vec<_> = parsed
+        .into_inner()
+        .flatten()
+        .filter(|p| p.as_rule() == Rule::param)
+        .collect();
+    if params.iter().all(|p| p.as_str() != "?") {
+        return Ok(statement.to_string());
+    }
+    if params.iter().any(|p| p.as_str() != "?") {
+        return Err("Use either ? or $n placeholders, not both".to_string());
+    }
+    let mut text = String::new();
+    let mut from = 0;
+    for (i, param) in params.iter().enumerate() {
+        text.push_str(&statement[from..param.as_span().start()]);
+        text.push_str(&format!("${}", i + 1));
+        from = param.as_span().end();
+    }
+    text.push_str(&statement[from..]);
+    Ok(text)
+}
+
+impl Prepared {
+    //how many values a bind takes
+    pub fn params(&self) -> usize {
+        self.params
+    }
+
+    //a copy of the statement with every placeholder replaced by its value, the types of the
+    //values are checked against the columns when the statement runs
+    pub fn bind(&self, values: &[Value]) -> Result<AstNode, String> {
+        if values.len() != self.params {
+            return Err(format!(
+                "Statement takes {} parameters, got {}",
+                self.params,
+                values.len()
+            ));
+        }
+        let mut ast = self.template.clone();
+        each_param(&mut ast, &mut |param| {
+            if let Expr::Param(index) = param {
+                *param = Expr::Value(values[*index].clone());
+            }
+        });
+        Ok(ast)
+    }
+
+    pub fn execute(&self, conn: &mut Connection, values: &[Value]) -> crate::Result<QueryResult> {
+        conn.run(self.bind(values)?)
+    }
+
+    //binds the values of a pick and returns its rows as they are read
+    pub fn query<'c>(&self, conn: &'c mut Connection, values: &[Value]) -> crate::Result<Rows<'c>> {
+        conn.rows(self.bind(values)?)
+    }
+}
+
+//calls `f` on every placeholder of a statement, subqueries included
+fn each_param(ast: &mut AstNode, f: &mut impl FnMut(&mut Expr)) {
+    match ast {
+        AstNode::Add { rows, .. } => {
+            for assignment in rows.iter_mut().flatten() {
+                expr_params(&mut assignment.value, f);
+            }
+        }
+        AstNode::Remove { conditions, .. } => condition_params(conditions, f),
+        AstNode::Pick {
+            columns, clauses, ..
+        } => {
+            selective_params(columns, f);
+            condition_params(&mut clauses.having, f);
+        }
+        AstNode::ConditionalPick {
+            columns,
+            conditions,
+            clauses,
+            ..
+        } => {
+            selective_params(columns, f);
+            condition_params(conditions, f);
+            condition_params(&mut clauses.having, f);
+        }
+        AstNode::Explain(pick) => each_param(pick, f),
+        _ => {}
+    }
+}
+
+fn selective_params(columns: &mut [Selective], f: &mut impl FnMut(&mut Expr)) {
+    for column in columns {
+        if let Selective::Expr { expr, .. } = column {
+            expr_params(expr, f);
+        }
+    }
+}
+
+fn condition_params(conditions: &mut [Condition], f: &mut impl FnMut(&mut Expr)) {
+    for condition in conditions {
+        if let Some(left) = &mut condition.left {
+            expr_params(left, f);
+        }
+        match &mut condition.test {
+            Test::Compare(_, right) => expr_params(right, f),
+            Test::In(values) => values.iter_mut().for_each(|v| expr_params(v, f)),
+            Test::Between(low, high) => {
+                expr_params(low, f);
+                expr_params(high, f);
+            }
+            Test::InPick(pick) | Test::Exists(pick) => each_param(pick, f),
+        }
+    }
+}
+
+fn expr_params(expr: &mut Expr, f: &mut impl FnMut(&mut Expr)) {
+    match expr {
+        Expr::Param(_) => f(expr),
+        Expr::Column(_) | Expr::Literal(_) | Expr::Value(_) => {}
+        Expr::Binary { left, right, .. } | Expr::Compare { left, right, .. } => {
+            expr_params(left, f);
+            expr_params(right, f);
+        }
+        Expr::Call { args, .. } => args.iter_mut().for_each(|a| expr_params(a, f)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_are_counted_by_number() {
+        assert_eq!(prepare("t.add( ?, ? )").unwrap().params(), 2);
+        assert_eq!(
+            prepare("t.pick( a ) where ( a == $2, b == $1, c == $2 )")
+                .unwrap()
+                .params(),
+            2
+        );
+        assert_eq!(prepare("t.pick( a )").unwrap().params(), 0);
+
+        let unused = prepare("t.pick( a ) where ( a == $2 )").unwrap_err();
+        assert_eq!(unused, "Placeholder $1 is never used");
+        assert!(prepare("t.pick( a ) where ( a == ?, b == $1 )").is_err());
+        assert!(prepare("make table t( a : int check ( a > ? ) )").is_err());
+        assert!(prepare("make view v as t.pick( a ) where ( a == ? )").is_err());
+    }
+
+    #[test]
+    fn binding_takes_exactly_one_value_per_placeholder() {
+        let prepared = prepare("t.add( ?, ? )").unwrap();
+        assert!(prepared.bind(&[Value::Int(1)]).is_err());
+        let AstNode::Add { rows, .. } = prepared.bind(&[Value::Int(1), Value::Null]).unwrap()
+        else {
+            panic!("not an add");
+        };
+        assert!(matches!(rows[0][0].value, Expr::Value(Value::Int(1))));
+        assert!(matches!(rows[0][1].value, Expr::Value(Value::Null)));
+    }
+}
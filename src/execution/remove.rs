@@ -0,0 +1,243 @@
+use super::{check_condition, key_of, open_data_file, satisfies};
+use crate::storage::{
+    read_database, read_key_index, read_table_info, remove_keys, table_pages, write_database,
+    write_rows_page,
+};
+use crate::structures::{
+    ActiveDataBase, Cell, Condition, Database, OnDelete, QueryResult, Row, TableInfo, TableRow,
+};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+
+//the rows a remove takes out and the references it clears, kept in memory until every
+//on delete action is known. a table is read whole the first time it is needed
+struct Removal<'a> {
+    data_file: &'a mut File,
+    table_info: &'a TableInfo,
+    db: &'a Database,
+    pages: HashMap<String, Vec<(i32, TableRow)>>,
+    changed: HashSet<(String, i32)>, //the pages to write back
+    removed: BTreeMap<String, Vec<Row>>,
+    nulled: BTreeMap<String, usize>,
+}
+
+impl Removal<'_> {
+    fn load(&mut self, table: &str) -> Result<&mut Vec<(i32, TableRow)>, String> {
+        Ok(match self.pages.entry(table.to_string()) {
+            Entry::Occupied(loaded) => loaded.into_mut(),
+            Entry::Vacant(missing) => {
+                missing.insert(table_pages(self.data_file, self.table_info, table)?)
+            }
+        })
+    }
+
+    //takes the rows of a table `matches` picks out, then runs the on delete actions of the
+    //columns referencing it
+    fn take(
+        &mut self,
+        table: &str,
+        matches: impl Fn(&Row) -> Result<bool, String>,
+    ) -> Result<(), String> {
+        let mut taken = Vec::new();
+        let mut changed = Vec::new();
+        for (page, table_page) in self.load(table)?.iter_mut() {
+            let before = taken.len();
+            let mut kept = Vec::new();
+            for row in std::mem::take(&mut table_page.rows) {
+                if matches(&row)? {
+                    taken.push(row);
+                } else {
+                    kept.push(row);
+                }
+            }
+            table_page.rows = kept;
+            if taken.len() != before {
+                changed.push(*page);
+            }
+        }
+        if taken.is_empty() {
+            return Ok(());
+        }
+        self.changed
+            .extend(changed.into_iter().map(|page| (table.to_string(), page)));
+        self.removed
+            .entry(table.to_string())
+            .or_default()
+            .extend(taken.iter().cloned());
+        self.referenced(table, &taken)
+    }
+
+    //sets a referencing column of the rows `refers` picks to null, a value that goes away
+    //with it may itself be referenced
+    fn set_null(
+        &mut self,
+        table: &str,
+        col: usize,
+        refers: impl Fn(&Row) -> bool,
+    ) -> Result<(), String> {
+        let mut before = Vec::new();
+        let mut changed = Vec::new();
+        for (page, table_page) in self.load(table)?.iter_mut() {
+            let count = before.len();
+            for row in table_page.rows.iter_mut().filter(|r| refers(r)) {
+                before.push(row.clone());
+                row.cells[col] = Cell::null();
+            }
+            if before.len() != count {
+                changed.push(*page);
+            }
+        }
+        self.changed
+            .extend(changed.into_iter().map(|page| (table.to_string(), page)));
+        *self.nulled.entry(table.to_string()).or_default() += before.len();
+        self.referenced(table, &before)
+    }
+
+    //a value of a referenced column that no row of the table holds anymore takes the child
+    //rows along (cascade), leaves them without a parent (set null) or stops the remove
+    //(restrict)
+    fn referenced(&mut self, table: &str, gone_rows: &[Row]) -> Result<(), String> {
+        let db = self.db;
+        let Some(schema) = db.table_details.iter().find(|t| t.name == table) else {
+            return Ok(());
+        };
+        for child in &db.table_details {
+            for (col, attr) in child.attributes.iter().enumerate() {
+                let Some(fk) = attr.references.as_ref().filter(|fk| fk.table == table) else {
+                    continue;
+                };
+                let Some(parent_col) = schema
+                    .attributes
+                    .iter()
+                    .position(|a| a.col_name == fk.column)
+                else {
+                    continue;
+                };
+                let datatype = schema.attributes[parent_col].datatype;
+                let values = |rows: &mut dyn Iterator<Item = &Row>| -> HashSet<String> {
+                    rows.map(|r| &r.cells[parent_col])
+                        .filter(|c| !c.null)
+                        .map(|c| key_of(c, datatype))
+                        .collect()
+                };
+                let remaining = values(&mut self.load(table)?.iter().flat_map(|(_, p)| &p.rows));
+                let gone: HashSet<String> = values(&mut gone_rows.iter())
+                    .difference(&remaining)
+                    .cloned()
+                    .collect();
+                if gone.is_empty() {
+                    continue;
+                }
+                let refers = |row: &Row| {
+                    let cell = &row.cells[col];
+                    !cell.null && gone.contains(&key_of(cell, datatype))
+                };
+                match fk.on_delete {
+                    OnDelete::Restrict => {
+                        let rows = self.load(&child.name)?;
+                        if let Some(row) =
+                            rows.iter().flat_map(|(_, p)| &p.rows).find(|r| refers(r))
+                        {
+                            return Err(format!(
+                                "Foreign key violation: {}({}) value {} is still referenced by {}({})",
+                                table, fk.column, row.cells[col].value, child.name, attr.col_name
+                            ));
+                        }
+                    }
+                    OnDelete::Cascade => self.take(&child.name, |row| Ok(refers(row)))?,
+                    OnDelete::SetNull => self.set_null(&child.name, col, refers)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+//removes the rows of a table meeting every condition, together with whatever the on delete
+//actions of the columns referencing it do. nothing is written before all of them are known
+pub fn remove(
+    active_db: &ActiveDataBase,
+    table: String,
+    conditions: Vec<Condition>,
+) -> Result<QueryResult, String> {
+    let mut data_file = open_data_file(&active_db.path)?;
+    let mut decodeddb = read_database(&mut data_file)?;
+    let decodedtable = read_table_info(&mut data_file)?;
+    let schema = decodeddb.table_details.iter().find(|t| t.name == table);
+    let Some(schema) = schema.filter(|_| decodedtable.tables.contains_key(&table)) else {
+        return Err(format!("Table {} not found", table));
+    };
+    for condition in &conditions {
+        check_condition(condition, &schema.attributes, &active_db.functions)
+            .map_err(|e| format!("{}, no rows removed", e))?;
+    }
+
+    let mut removal = Removal {
+        data_file: &mut data_file,
+        table_info: &decodedtable,
+        db: &decodeddb,
+        pages: HashMap::new(),
+        changed: HashSet::new(),
+        removed: BTreeMap::new(),
+        nulled: BTreeMap::new(),
+    };
+    removal
+        .take(&table, |row| {
+            satisfies(row, &schema.attributes, &conditions, &active_db.functions)
+        })
+        .map_err(|e| format!("{}, no rows removed", e))?;
+    let Removal {
+        pages,
+        changed,
+        removed,
+        nulled,
+        ..
+    } = removal;
+
+    for (name, table_pages) in &pages {
+        for (page, table_page) in table_pages {
+            if changed.contains(&(name.clone(), *page)) {
+                write_rows_page(&mut data_file, *page, table_page)?;
+            }
+        }
+    }
+    for (name, rows) in &removed {
+        let Some(mut index) = read_key_index(&mut data_file, &decodedtable, name)? else {
+            continue;
+        };
+        let attributes = &decodeddb
+            .table_details
+            .iter()
+            .find(|t| &t.name == name)
+            .unwrap()
+            .attributes;
+        let key_col = attributes.iter().position(|a| a.key).unwrap();
+        let keys = rows
+            .iter()
+            .map(|r| key_of(&r.cells[key_col], attributes[key_col].datatype))
+            .collect();
+        remove_keys(&mut data_file, &mut index, keys)?;
+    }
+    for schema in decodeddb.table_details.iter_mut() {
+        if let Some(rows) = removed.get(&schema.name) {
+            schema.rows = schema.rows.saturating_sub(rows.len());
+        }
+    }
+    if !removed.is_empty() {
+        write_database(&mut data_file, &decodeddb)?;
+    }
+
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let count = removed.get(&table).map_or(0, |rows| rows.len());
+    let mut done = vec![format!("{} Row{} removed", count, plural(count))];
+    for (name, rows) in &removed {
+        if name != &table {
+            done.push(format!("{} removed from {}", rows.len(), name));
+        }
+    }
+    for (name, count) in &nulled {
+        done.push(format!("{} set null in {}", count, name));
+    }
+    Ok(QueryResult::done(done.join("\n"), count))
+}
@@ -0,0 +1,328 @@
+use crate::structures::{Cell, DataType, Row};
+use std::cmp::Ordering;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::vec;
+
+//rough amount of row data kept in memory before a sorted run is spilled to a scratch file
+const SORT_MEMORY: usize = 1024 * 1024;
+
+static SCRATCH_FILES: AtomicUsize = AtomicUsize::new(0);
+
+//one `order by` key resolved against the schema
+#[derive(Clone, Copy)]
+pub struct SortColumn {
+    pub index: usize,
+    pub datatype: DataType,
+    pub descending: bool,
+}
+
+//nulls sort before every value, like in SQLite
+pub fn compare_cells(a: &Cell, b: &Cell, datatype: DataType) -> Ordering {
+    match (a.null, b.null) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Less,
+        (false, true) => return Ordering::Greater,
+        _ => {}
+    }
+    match datatype {
+        DataType::Int => match (a.value.parse::<i32>(), b.value.parse::<i32>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            //averages are the only fractions an int column holds
+            _ => match (a.value.parse::<f64>(), b.value.parse::<f64>()) {
+                (Ok(x), Ok(y)) => x.total_cmp(&y),
+                _ => a.value.cmp(&b.value),
+            },
+        },
+        DataType::Bool => match (a.value.parse::<bool>(), b.value.parse::<bool>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => a.value.cmp(&b.value),
+        },
+        DataType::String => a.value.cmp(&b.value),
+    }
+}
+
+pub fn compare_rows(a: &Row, b: &Row, keys: &[SortColumn]) -> Ordering {
+    for key in keys {
+        let order = compare_cells(&a.cells[key.index], &b.cells[key.index], key.datatype);
+        let order = if key.descending {
+            order.reverse()
+        } else {
+            order
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    Ordering::Equal
+}
+
+fn row_size(row: &Row) -> usize {
+    row.cells.iter().map(|c| c.value.len() + 32).sum::<usize>() + 24
+}
+
+//sorts rows that fit in memory directly; bigger inputs are cut into sorted runs that are
+//written to a scratch file and merged back while the caller reads the result
+pub fn sort_rows(
+    rows: impl IntoIterator<Item = Result<Row, String>>,
+    keys: Vec<SortColumn>,
+) -> Result<SortedRows, String> {
+    let mut buffer: Vec<Row> = Vec::new();
+    let mut used = 0;
+    let mut spill: Option<Spill> = None;
+
+    for row in rows {
+        let row = row?;
+        used += row_size(&row);
+        buffer.push(row);
+        if used > SORT_MEMORY {
+            buffer.sort_by(|a, b| compare_rows(a, b, &keys));
+            let spill = match &mut spill {
+                Some(spill) => spill,
+                None => spill.insert(Spill::new()?),
+            };
+            spill.write_run(std::mem::take(&mut buffer))?;
+            used = 0;
+        }
+    }
+    buffer.sort_by(|a, b| compare_rows(a, b, &keys));
+
+    match spill {
+        None => Ok(SortedRows::Memory(buffer.into_iter())),
+        Some(mut spill) => {
+            if !buffer.is_empty() {
+                spill.write_run(buffer)?;
+            }
+            let runs = spill
+                .runs
+                .iter()
+                .map(|&(start, end)| RunReader::new(&mut spill.file, start, end))
+                .collect::<Result<_, _>>()?;
+            Ok(SortedRows::Merge { spill, runs, keys })
+        }
+    }
+}
+
+fn scratch_error(action: &str, e: impl std::fmt::Display) -> String {
+    format!("Problem {} the sort scratch file: {}", action, e)
+}
+
+//scratch file holding the sorted runs one after the other, deleted once the merge is
+//dropped. every row is a 4 byte length followed by the row itself, so a row may be as
+//wide as it likes
+pub struct Spill {
+    path: PathBuf,
+    file: File,
+    runs: Vec<(u64, u64)>, //where each run starts and ends in the file
+    end: u64,
+}
+
+impl Spill {
+    fn new() -> Result<Self, String> {
+        let path = std::env::temp_dir().join(format!(
+            "runedb-sort-{}-{}.tmp",
+            std::process::id(),
+            SCRATCH_FILES.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| scratch_error("creating", e))?;
+        Ok(Spill {
+            path,
+            file,
+            runs: Vec::new(),
+            end: 0,
+        })
+    }
+
+    fn write_run(&mut self, rows: Vec<Row>) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        for row in &rows {
+            let record = rmp_serde::to_vec(row).map_err(|e| scratch_error("writing", e))?;
+            bytes.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&record);
+        }
+        self.file
+            .seek(SeekFrom::Start(self.end))
+            .and_then(|_| self.file.write_all(&bytes))
+            .map_err(|e| scratch_error("writing", e))?;
+        let start = self.end;
+        self.end += bytes.len() as u64;
+        self.runs.push((start, self.end));
+        Ok(())
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+//reads one run back a row at a time
+pub struct RunReader {
+    next: u64,
+    end: u64,
+    head: Option<Row>,
+}
+
+impl RunReader {
+    fn new(file: &mut File, start: u64, end: u64) -> Result<Self, String> {
+        let mut reader = RunReader {
+            next: start,
+            end,
+            head: None,
+        };
+        reader.head = reader.pull(file)?;
+        Ok(reader)
+    }
+
+    fn pull(&mut self, file: &mut File) -> Result<Option<Row>, String> {
+        if self.next >= self.end {
+            return Ok(None);
+        }
+        let mut len = [0u8; 4];
+        file.seek(SeekFrom::Start(self.next))
+            .and_then(|_| file.read_exact(&mut len))
+            .map_err(|e| scratch_error("reading", e))?;
+        let mut record = vec![0u8; u32::from_le_bytes(len) as usize];
+        file.read_exact(&mut record)
+            .map_err(|e| scratch_error("reading", e))?;
+        self.next += 4 + record.len() as u64;
+        rmp_serde::from_slice(&record)
+            .map(Some)
+            .map_err(|e| scratch_error("reading", e))
+    }
+}
+
+pub enum SortedRows {
+    Memory(vec::IntoIter<Row>),
+    Merge {
+        spill: Spill,
+        runs: Vec<RunReader>,
+        keys: Vec<SortColumn>,
+    },
+}
+
+//a run that can not be read back ends the merge after its error
+impl Iterator for SortedRows {
+    type Item = Result<Row, String>;
+
+    fn next(&mut self) -> Option<Result<Row, String>> {
+        match self {
+            SortedRows::Memory(rows) => rows.next().map(Ok),
+            SortedRows::Merge { spill, runs, keys } => {
+                //the earliest run wins ties, which keeps the sort stable
+                let mut best: Option<usize> = None;
+                for (i, run) in runs.iter().enumerate() {
+                    if let Some(head) = &run.head {
+                        let better = match best {
+                            None => true,
+                            Some(b) => {
+                                compare_rows(head, runs[b].head.as_ref().unwrap(), keys)
+                                    == Ordering::Less
+                            }
+                        };
+                        if better {
+                            best = Some(i);
+                        }
+                    }
+                }
+                let run = &mut runs[best?];
+                let row = run.head.take()?;
+                match run.pull(&mut spill.file) {
+                    Ok(head) => run.head = head,
+                    Err(e) => {
+                        runs.clear();
+                        return Some(Err(e));
+                    }
+                }
+                Some(Ok(row))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(values: &[&str]) -> Row {
+        Row {
+            cells: values.iter().map(|v| Cell::new(v.to_string())).collect(),
+        }
+    }
+
+    fn sorted(rows: Vec<Row>, keys: Vec<SortColumn>) -> SortedRows {
+        sort_rows(rows.into_iter().map(Ok), keys).unwrap()
+    }
+
+    #[test]
+    fn nulls_sort_first_and_ints_by_value() {
+        let (two, ten) = (Cell::new("2".to_string()), Cell::new("10".to_string()));
+        assert_eq!(compare_cells(&two, &ten, DataType::Int), Ordering::Less);
+        assert_eq!(
+            compare_cells(&two, &ten, DataType::String),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_cells(&Cell::null(), &two, DataType::Int),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn spilled_runs_merge_in_order_and_stay_stable() {
+        //far more than fits in memory, so several runs go through the scratch file
+        let count = 30000;
+        let rows = (0..count)
+            .map(|i| row(&[&(i % 7).to_string(), &i.to_string()]))
+            .collect();
+        let keys = vec![SortColumn {
+            index: 0,
+            datatype: DataType::Int,
+            descending: true,
+        }];
+        let sorted = sorted(rows, keys);
+        assert!(matches!(sorted, SortedRows::Merge { .. }));
+        let sorted: Vec<(i32, i32)> = sorted
+            .map(|r| {
+                let r = r.unwrap();
+                (
+                    r.cells[0].value.parse().unwrap(),
+                    r.cells[1].value.parse().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(sorted.len(), count);
+        for pair in sorted.windows(2) {
+            let ((key, seq), (next_key, next_seq)) = (pair[0], pair[1]);
+            assert!(key > next_key || (key == next_key && seq < next_seq));
+        }
+    }
+
+    #[test]
+    fn rows_wider_than_a_page_are_spilled() {
+        let width = 2 * crate::storage::PAGE_SIZE;
+        let rows = (0..300)
+            .map(|i| row(&[&format!("{:03}", (i * 7) % 300).repeat(width / 3)]))
+            .collect();
+        let keys = vec![SortColumn {
+            index: 0,
+            datatype: DataType::String,
+            descending: false,
+        }];
+        let sorted = sorted(rows, keys);
+        assert!(matches!(sorted, SortedRows::Merge { .. }));
+        let sorted: Vec<String> = sorted.map(|r| r.unwrap().cells[0].value.clone()).collect();
+        assert_eq!(sorted.len(), 300);
+        assert!(sorted.iter().all(|v| v.len() >= width - 2));
+        assert!(sorted.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
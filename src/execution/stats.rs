@@ -0,0 +1,43 @@
+use super::key_of;
+use super::sort::compare_cells;
+use crate::structures::{Attr, Cell, ColumnStats, Row};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+//reads every row once: the row count, and for each column its distinct values and the
+//smallest and largest of them, nulls left out
+pub fn collect_stats(
+    rows: impl Iterator<Item = Result<Row, String>>,
+    attributes: &[Attr],
+) -> Result<(usize, Vec<ColumnStats>), String> {
+    let mut count = 0;
+    let mut seen: Vec<HashSet<String>> = vec![HashSet::new(); attributes.len()];
+    let mut bounds: Vec<Option<(Cell, Cell)>> = vec![None; attributes.len()];
+    for row in rows {
+        let row = row?;
+        count += 1;
+        for (i, (cell, attr)) in row.cells.iter().zip(attributes).enumerate() {
+            if cell.null {
+                continue;
+            }
+            seen[i].insert(key_of(cell, attr.datatype));
+            let bound = bounds[i].get_or_insert_with(|| (cell.clone(), cell.clone()));
+            if compare_cells(cell, &bound.0, attr.datatype) == Ordering::Less {
+                bound.0 = cell.clone();
+            }
+            if compare_cells(cell, &bound.1, attr.datatype) == Ordering::Greater {
+                bound.1 = cell.clone();
+            }
+        }
+    }
+    let stats = seen
+        .into_iter()
+        .zip(bounds)
+        .map(|(values, bound)| ColumnStats {
+            distinct: values.len(),
+            min: bound.as_ref().map(|(min, _)| min.value.clone()),
+            max: bound.map(|(_, max)| max.value),
+        })
+        .collect();
+    Ok((count, stats))
+}
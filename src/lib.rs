@@ -0,0 +1,11 @@
+//RuneDB as a library: `RuneDB::open` gives a connection to a data file, its statements are
+//the ones the REPL takes
+mod connection;
+mod execution;
+mod parser;
+mod storage;
+mod structures;
+
+pub use connection::{Connection, Error, Result, Rows, RuneDB};
+pub use execution::{Prepared, ScalarFunction};
+pub use structures::{Column, DataType, QueryResult, Value};
@@ -1,17 +1,54 @@
-mod parser;
-use crate::structures::ActiveDataBase;
-pub mod execution;
-mod structures;
 use std::io::{self, Write};
-use crate::parser::parse_input;
-use execution::{execute, build_ast};
-use std::sync::Mutex;
-use lazy_static::lazy_static;
+use runedb::{Rows, RuneDB};
 
-lazy_static! {
-    pub static ref ACTIVE_DB: Mutex<Option<ActiveDataBase>> = Mutex::new(None);
-}
+//rows read before the widths of the columns are fixed, later rows print as they arrive
+const SIZED_ROWS: usize = 100;
 
+//a pick as a table, every column as wide as its longest value among the first rows
+fn render(rows: Rows) {
+    let columns: Vec<String> = rows.columns().iter().map(|c| c.name.clone()).collect();
+    //a row that can not be read ends the table, its error is printed below it
+    let mut failed = None;
+    let mut rows = rows.map_while(|row| match row {
+        Ok(row) => Some(row.iter().map(|v| v.to_string()).collect::<Vec<String>>()),
+        Err(e) => {
+            failed = Some(e);
+            None
+        }
+    });
+    let first: Vec<Vec<String>> = rows.by_ref().take(SIZED_ROWS).collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            first
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain(std::iter::once(name.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |cells: &[String]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(&columns);
+    line(&widths.iter().map(|&width| "-".repeat(width)).collect::<Vec<_>>());
+    let mut count = 0;
+    for row in first.into_iter().chain(rows) {
+        line(&row);
+        count += 1;
+    }
+    println!("({} row{})", count, if count == 1 { "" } else { "s" });
+    if let Some(e) = failed {
+        println!("{}", e);
+    }
+}
 
 fn main() {
     const HELP: &str = "Functionalities ->
@@ -20,13 +57,35 @@ create databases, make tables, insert rows, pick rows using syntax similar to Py
 Commands:
   Create a new Database: make rdb <database-name>
   Create a table: make table <table-name>( <attr-name> : datatype, ... )
-  Insert rows: <table-name>.add( <attr-name> = value, ... )
+  Foreign key column: <attr-name> : datatype references <table-name>(<attr-name>) [on delete restrict|cascade|set null] (a key replaced by put or on conflict can not take away a value still referenced)
+  Column default and check: <attr-name> : datatype default value check ( <attr-name> op value, ... )
+  Auto-increment column: <attr-name> : int autoincrement (or serial)
+  Key column: <attr-name> : datatype key
+  Save a pick as a view: make view <view-name> as <table-name>.pick( ... ) ..., then pick from it like a table: <view-name>.pick( ... )
+  Insert rows: <table-name>.add( <attr-name> = value, ... ) or <table-name>.add( value, ... )
+  Insert many rows: <table-name>.add( ( value, ... ), ( value, ... ), ... )
+  Insert or replace by key: <table-name>.put( value, ... ) or <table-name>.add( ... ) on conflict replace|ignore|update
+  Remove rows: <table-name>.remove( <attr-name> == value, ... ) (rows referencing them are kept out by restrict, removed by cascade or set null)
   Select columns: <table-name>.pick( <attr-name>, ... )
-  Select with condition: <table-name>.pick( <attr-name>, ... ) where ( <attr-name> = value, ... )
+  Select without repeated rows: <table-name>.pick( distinct <attr-name>, ... )
+  Select with condition: <table-name>.pick( <attr-name>, ... ) where ( <attr-name> == value, ... )
+  Text conditions: <attr-name> like|ilike|contains|starts with|ends with|matches 'pattern' (like: % any run, _ one character; matches: regular expression)
+  More conditions: <attr-name> in ( value, ... ) | <attr-name> between value and value
+  Subqueries: <attr-name> in ( <table-name>.pick( <attr-name> ) ... ) | exists ( <table-name>.pick( ... ) ... )
+  Join tables: <table-name>.join|left join( <table-name> on <attr-name> op <attr-name>, ... ).pick( <table-name>.<attr-name>, ... )
+  Expressions: <table-name>.pick( <attr-name> * <attr-name> as <alias>, ... ) where ( <attr-name> + 1 > <attr-name>, ... ) with + - * / %
+  Functions: upper lower length trim substr(text, start, length) concat | abs round(number, digits) mod | coalesce if(test, then, else) case(test, value, ..., else) | today year month day date_add(date, days) days_between (dates as yyyy-mm-dd)
+  Aggregates: <table-name>.pick( count(*), count|sum|avg|min|max( <attr-name> ), ... ) [where ( ... )]
+  Group rows: <table-name>.pick( <attr-name>, count(*), ... ) group by <attr-name>, ... [having ( count(*) op value, ... )]
+  Order results: <table-name>.pick( ... ) order by <attr-name>|<aggregate> [asc|desc], ...
+  Page through results: <table-name>.pick( ... ) [order by ...] limit n offset m
+  Show how a pick runs: explain <table-name>.pick( ... ) ... (steps with estimated rows, key lookups when the where-clause names keys)
+  Collect statistics for the planner: analyze [<table-name>] (row counts, distinct values, min and max of every column)
 
 Note: nested AND/OR conditions not yet supported.";
 
     println!("Welcome to RuneDB! Type 'help' for commands, or 'quit' to exit.");
+    let mut conn = RuneDB::connect();
 
     loop {
         print!("db> ");
@@ -44,14 +103,21 @@ Note: nested AND/OR conditions not yet supported.";
             continue;
         }
 
-        let parse_result = parse_input(input);
-        //print!("{:#?}", parse_result);
-        let inner_command = parse_result.into_inner().next().unwrap();
-        
-        let ast = build_ast(inner_command);
-        //print!("{:#?}\n", ast);
-        execute(ast);
-        
+        if input.is_empty() {
+            continue;
+        }
+
+        if conn.returns_rows(input) {
+            match conn.query(input) {
+                Ok(rows) => render(rows),
+                Err(e) => println!("{}", e),
+            }
+            continue;
+        }
+        match conn.execute(input) {
+            Ok(result) => println!("{}", result.message.unwrap_or_default()),
+            Err(e) => println!("{}", e),
+        }
     }
 }
 
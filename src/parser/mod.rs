@@ -5,10 +5,8 @@ use pest_derive::Parser;
 #[grammar = "parser/syntax.pest"]
 pub struct MyParser;
 
-
-pub fn parse_input(input: &str) -> pest::iterators::Pair<'_, Rule> {
+pub fn parse_input(input: &str) -> Result<pest::iterators::Pair<'_, Rule>, String> {
     MyParser::parse(Rule::main, input)
-        .expect("parse failed")
-        .next()
-        .unwrap()
+        .map(|mut pairs| pairs.next().unwrap())
+        .map_err(|_| format!("{} does not parse", input))
 }
@@ -0,0 +1,542 @@
+use crate::structures::{
+    ColumnStats, Database, KeyEntry, KeyPage, Row, StatsPage, TableInfo, TableRow,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub const PAGE_SIZE: usize = 4096;
+//room kept free in every table page for the msgpack headers around its rows
+const PAGE_HEADER: usize = 16;
+
+//page 0 holds the Database catalog, page 1 the TableInfo directory,
+//every page after that belongs to the row chain, the key index chain or the statistics
+//chain of one table
+
+//a page that links to the next page of the same chain, 0 ending the chain
+trait Chained: Default + Serialize + DeserializeOwned {
+    type Item: Serialize;
+    fn items(&mut self) -> &mut Vec<Self::Item>;
+    fn set_next(&mut self, next: i32);
+}
+
+impl Chained for TableRow {
+    type Item = Row;
+    fn items(&mut self) -> &mut Vec<Row> {
+        &mut self.rows
+    }
+    fn set_next(&mut self, next: i32) {
+        self.next = next;
+    }
+}
+
+impl Chained for StatsPage {
+    type Item = ColumnStats;
+    fn items(&mut self) -> &mut Vec<ColumnStats> {
+        &mut self.columns
+    }
+    fn set_next(&mut self, next: i32) {
+        self.next = next;
+    }
+}
+
+impl Chained for KeyPage {
+    type Item = KeyEntry;
+    fn items(&mut self) -> &mut Vec<KeyEntry> {
+        &mut self.entries
+    }
+    fn set_next(&mut self, next: i32) {
+        self.next = next;
+    }
+}
+
+fn read_error(page: i32, e: io::Error) -> String {
+    format!("Problem reading page {} of the data file: {}", page, e)
+}
+
+fn write_error(page: i32, e: io::Error) -> String {
+    format!("Problem writing page {} of the data file: {}", page, e)
+}
+
+//a page past the end of the file reads as empty, the last page of a file may be short
+pub fn read_page(data_file: &mut File, page: i32) -> Result<Vec<u8>, String> {
+    data_file
+        .seek(SeekFrom::Start(page as u64 * PAGE_SIZE as u64))
+        .map_err(|e| read_error(page, e))?;
+    let mut buf = vec![0u8; PAGE_SIZE];
+    let mut filled = 0;
+    while filled < PAGE_SIZE {
+        match data_file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(read_error(page, e)),
+        }
+    }
+    buf.truncate(filled);
+    Ok(buf)
+}
+
+//pages are always written whole so leftovers of an older, longer page never survive
+pub fn write_page(data_file: &mut File, page: i32, bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() > PAGE_SIZE {
+        return Err(format!(
+            "Page {} needs {} bytes but a page only has {}",
+            page,
+            bytes.len(),
+            PAGE_SIZE
+        ));
+    }
+    let mut buf = bytes.to_vec();
+    buf.resize(PAGE_SIZE, 0);
+    data_file
+        .seek(SeekFrom::Start(page as u64 * PAGE_SIZE as u64))
+        .map_err(|e| write_error(page, e))?;
+    data_file.write_all(&buf).map_err(|e| write_error(page, e))
+}
+
+pub fn read_database(data_file: &mut File) -> Result<Database, String> {
+    rmp_serde::from_slice(&read_page(data_file, 0)?)
+        .map_err(|e| format!("The catalog of the data file does not decode: {}", e))
+}
+
+//the catalog and the directory each have a single page, a write that would overflow it is
+//refused before anything is written
+fn directory_bytes<T: Serialize>(value: &T, what: &str) -> Result<Vec<u8>, String> {
+    let bytes = rmp_serde::to_vec(value).unwrap();
+    if bytes.len() > PAGE_SIZE {
+        return Err(format!(
+            "The {} needs {} bytes but only has a page of {}",
+            what,
+            bytes.len(),
+            PAGE_SIZE
+        ));
+    }
+    Ok(bytes)
+}
+
+//fails when the catalog would no longer fit its page, so a change that has to be saved
+//there can be refused before anything else is written
+pub fn check_database(db: &Database) -> Result<(), String> {
+    directory_bytes(db, "catalog").map(|_| ())
+}
+
+pub fn write_database(data_file: &mut File, db: &Database) -> Result<(), String> {
+    write_page(data_file, 0, &directory_bytes(db, "catalog")?)
+}
+
+pub fn read_table_info(data_file: &mut File) -> Result<TableInfo, String> {
+    rmp_serde::from_slice(&read_page(data_file, 1)?).map_err(|e| {
+        format!(
+            "The table directory of the data file does not decode: {}",
+            e
+        )
+    })
+}
+
+pub fn write_table_info(data_file: &mut File, table_info: &TableInfo) -> Result<(), String> {
+    write_page(
+        data_file,
+        1,
+        &directory_bytes(table_info, "table directory")?,
+    )
+}
+
+//first page past both the end of the file and every page the directory knows about
+pub fn free_page(data_file: &mut File, table_info: &TableInfo) -> Result<i32, String> {
+    let file_pages = data_file
+        .metadata()
+        .map_err(|e| format!("Problem reading the size of the data file: {}", e))?
+        .len()
+        .div_ceil(PAGE_SIZE as u64) as i32;
+    let known = table_info
+        .tables
+        .values()
+        .chain(table_info.tails.values())
+        .chain(table_info.indexes.values())
+        .chain(table_info.stats.values())
+        .map(|&p| p + 1)
+        .max()
+        .unwrap_or(0);
+    Ok(file_pages.max(known).max(2))
+}
+
+fn read_chain_page<P: Chained>(data_file: &mut File, page: i32) -> Result<P, String> {
+    let bytes = read_page(data_file, page)?;
+    if bytes.is_empty() {
+        Ok(P::default())
+    } else {
+        rmp_serde::from_slice(&bytes)
+            .map_err(|e| format!("Page {} of the data file does not decode: {}", page, e))
+    }
+}
+
+pub fn read_rows_page(data_file: &mut File, page: i32) -> Result<TableRow, String> {
+    read_chain_page(data_file, page)
+}
+
+pub fn write_rows_page(data_file: &mut File, page: i32, rows: &TableRow) -> Result<(), String> {
+    write_page(data_file, page, &rmp_serde::to_vec(rows).unwrap())
+}
+
+//gives a new table its first, empty page, and an empty key index when it has a key column
+pub fn create_table(
+    data_file: &mut File,
+    table_info: &mut TableInfo,
+    table: &str,
+    keyed: bool,
+) -> Result<(), String> {
+    let page = free_page(data_file, table_info)?;
+    write_rows_page(data_file, page, &TableRow::default())?;
+    table_info.tables.insert(table.to_string(), page);
+    table_info.tails.insert(table.to_string(), page);
+    if keyed {
+        let index = free_page(data_file, table_info)?;
+        write_page(
+            data_file,
+            index,
+            &rmp_serde::to_vec(&KeyPage::default()).unwrap(),
+        )?;
+        table_info.indexes.insert(table.to_string(), index);
+    }
+    Ok(())
+}
+
+//every row of the table, following the page chain from its first page
+pub fn scan_table(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    table: &str,
+) -> Result<Vec<Row>, String> {
+    TableScan::new(data_file, table_info, table).collect()
+}
+
+//reads the rows of a table lazily, one page at a time, so a consumer that stops
+//early never touches the rest of the chain. a page that can not be read ends the scan
+//after its error
+pub struct TableScan<'a> {
+    data_file: &'a mut File,
+    rows: std::vec::IntoIter<Row>,
+    next: i32,
+}
+
+impl<'a> TableScan<'a> {
+    pub fn new(data_file: &'a mut File, table_info: &TableInfo, table: &str) -> Self {
+        TableScan {
+            data_file,
+            rows: Vec::new().into_iter(),
+            next: table_info.tables.get(table).copied().unwrap_or(0),
+        }
+    }
+}
+
+impl Iterator for TableScan<'_> {
+    type Item = Result<Row, String>;
+
+    fn next(&mut self) -> Option<Result<Row, String>> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(Ok(row));
+            }
+            if self.next == 0 {
+                return None;
+            }
+            match read_rows_page(self.data_file, self.next) {
+                Ok(table_page) => {
+                    self.rows = table_page.rows.into_iter();
+                    self.next = table_page.next;
+                }
+                Err(e) => {
+                    self.next = 0;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+//every page of the table chain with its number, for rewriting rows where they are stored
+pub fn table_pages(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    table: &str,
+) -> Result<Vec<(i32, TableRow)>, String> {
+    let mut pages = Vec::new();
+    let mut page = table_info.tables.get(table).copied().unwrap_or(0);
+    while page != 0 {
+        let table_page = read_rows_page(data_file, page)?;
+        let next = table_page.next;
+        pages.push((page, table_page));
+        page = next;
+    }
+    Ok(pages)
+}
+
+fn tail_page(data_file: &mut File, table_info: &TableInfo, table: &str) -> Result<i32, String> {
+    if let Some(&tail) = table_info.tails.get(table) {
+        return Ok(tail);
+    }
+    let mut page = table_info.tables[table];
+    loop {
+        let next = read_rows_page(data_file, page)?.next;
+        if next == 0 {
+            return Ok(page);
+        }
+        page = next;
+    }
+}
+
+//appends items after the tail page of a chain: the tail is read once, every page touched
+//is written once and new pages are linked on as the current one fills up.
+//returns the page each item landed on and the new tail
+fn append_chain<P: Chained>(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    tail: i32,
+    items: Vec<P::Item>,
+) -> Result<(Vec<i32>, i32), String> {
+    let mut sizes = Vec::with_capacity(items.len());
+    for item in &items {
+        let size = rmp_serde::to_vec(item).unwrap().len();
+        if size + PAGE_HEADER > PAGE_SIZE {
+            return Err(format!("Entry of {} bytes does not fit in a page", size));
+        }
+        sizes.push(size);
+    }
+
+    let mut page = tail;
+    let mut current: P = read_chain_page(data_file, page)?;
+    let mut used = PAGE_HEADER
+        + current
+            .items()
+            .iter()
+            .map(|i| rmp_serde::to_vec(i).unwrap().len())
+            .sum::<usize>();
+    let mut next_free = free_page(data_file, table_info)?;
+    let mut pages = Vec::with_capacity(items.len());
+
+    for (item, size) in items.into_iter().zip(sizes) {
+        if used + size > PAGE_SIZE {
+            current.set_next(next_free);
+            write_page(data_file, page, &rmp_serde::to_vec(&current).unwrap())?;
+            page = next_free;
+            next_free += 1;
+            current = P::default();
+            used = PAGE_HEADER;
+        }
+        current.items().push(item);
+        pages.push(page);
+        used += size;
+    }
+    write_page(data_file, page, &rmp_serde::to_vec(&current).unwrap())?;
+    Ok((pages, page))
+}
+
+//appends rows at the end of the table chain, returns the page each row was stored on
+pub fn append_rows(
+    data_file: &mut File,
+    table_info: &mut TableInfo,
+    table: &str,
+    rows: Vec<Row>,
+) -> Result<Vec<i32>, String> {
+    let tail = tail_page(data_file, table_info, table)?;
+    let (pages, tail) = append_chain::<TableRow>(data_file, table_info, tail, rows)?;
+
+    if table_info.tails.get(table) != Some(&tail) {
+        table_info.tails.insert(table.to_string(), tail);
+        write_table_info(data_file, table_info)?;
+    }
+    Ok(pages)
+}
+
+//the statistics of a table in schema order, empty before it was first analyzed
+pub fn read_stats(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    table: &str,
+) -> Result<Vec<ColumnStats>, String> {
+    let mut columns = Vec::new();
+    let mut page = table_info.stats.get(table).copied().unwrap_or(0);
+    while page != 0 {
+        let stats_page: StatsPage = read_chain_page(data_file, page)?;
+        columns.extend(stats_page.columns);
+        page = stats_page.next;
+    }
+    Ok(columns)
+}
+
+//replaces the statistics of a table, writing over the pages of the earlier ones before
+//new pages are taken. a column whose smallest and largest values do not fit a page
+//together keeps only its count of distinct values
+pub fn write_stats(
+    data_file: &mut File,
+    table_info: &mut TableInfo,
+    table: &str,
+    columns: Vec<ColumnStats>,
+) -> Result<(), String> {
+    let mut old = Vec::new();
+    let mut page = table_info.stats.get(table).copied().unwrap_or(0);
+    while page != 0 {
+        old.push(page);
+        page = read_chain_page::<StatsPage>(data_file, page)?.next;
+    }
+
+    let mut stats_pages = vec![StatsPage::default()];
+    let mut used = PAGE_HEADER;
+    for mut column in columns {
+        let mut size = rmp_serde::to_vec(&column).unwrap().len();
+        if size + PAGE_HEADER > PAGE_SIZE {
+            column.min = None;
+            column.max = None;
+            size = rmp_serde::to_vec(&column).unwrap().len();
+        }
+        if used + size > PAGE_SIZE {
+            stats_pages.push(StatsPage::default());
+            used = PAGE_HEADER;
+        }
+        stats_pages.last_mut().unwrap().columns.push(column);
+        used += size;
+    }
+
+    let mut next_free = free_page(data_file, table_info)?;
+    let pages: Vec<i32> = (0..stats_pages.len())
+        .map(|i| {
+            old.get(i).copied().unwrap_or_else(|| {
+                next_free += 1;
+                next_free - 1
+            })
+        })
+        .collect();
+    for (i, mut stats_page) in stats_pages.into_iter().enumerate() {
+        stats_page.next = pages.get(i + 1).copied().unwrap_or(0);
+        write_page(
+            data_file,
+            pages[i],
+            &rmp_serde::to_vec(&stats_page).unwrap(),
+        )?;
+    }
+    if table_info.stats.get(table) != Some(&pages[0]) {
+        table_info.stats.insert(table.to_string(), pages[0]);
+        write_table_info(data_file, table_info)?;
+    }
+    Ok(())
+}
+
+//where a key's row lives, and which index page records that
+#[derive(Debug, Clone, Copy)]
+pub struct KeyLocation {
+    pub data_page: i32,
+    pub index_page: i32,
+}
+
+pub struct KeyIndex {
+    pub keys: HashMap<String, KeyLocation>,
+    tail: i32,
+}
+
+//the key index of a table, None when the table has no key column
+pub fn read_key_index(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    table: &str,
+) -> Result<Option<KeyIndex>, String> {
+    let Some(&first) = table_info.indexes.get(table) else {
+        return Ok(None);
+    };
+    let mut page = first;
+    let mut keys = HashMap::new();
+    loop {
+        let index_page: KeyPage = read_chain_page(data_file, page)?;
+        for entry in index_page.entries {
+            keys.insert(
+                entry.key,
+                KeyLocation {
+                    data_page: entry.page,
+                    index_page: page,
+                },
+            );
+        }
+        if index_page.next == 0 {
+            return Ok(Some(KeyIndex { keys, tail: page }));
+        }
+        page = index_page.next;
+    }
+}
+
+pub fn add_keys(
+    data_file: &mut File,
+    table_info: &TableInfo,
+    index: &mut KeyIndex,
+    entries: Vec<KeyEntry>,
+) -> Result<(), String> {
+    let keys: Vec<(String, i32)> = entries.iter().map(|e| (e.key.clone(), e.page)).collect();
+    let (pages, tail) = append_chain::<KeyPage>(data_file, table_info, index.tail, entries)?;
+    for ((key, data_page), index_page) in keys.into_iter().zip(pages) {
+        index.keys.insert(
+            key,
+            KeyLocation {
+                data_page,
+                index_page,
+            },
+        );
+    }
+    index.tail = tail;
+    Ok(())
+}
+
+//points keys at the data page their row was moved to
+pub fn move_keys(
+    data_file: &mut File,
+    index: &mut KeyIndex,
+    moved: Vec<(String, i32)>,
+) -> Result<(), String> {
+    let mut by_index_page: HashMap<i32, Vec<(String, i32)>> = HashMap::new();
+    for (key, data_page) in moved {
+        let location = index.keys.get_mut(&key).expect("moved key is not indexed");
+        location.data_page = data_page;
+        by_index_page
+            .entry(location.index_page)
+            .or_default()
+            .push((key, data_page));
+    }
+    for (page, keys) in by_index_page {
+        let mut index_page: KeyPage = read_chain_page(data_file, page)?;
+        for (key, data_page) in keys {
+            if let Some(entry) = index_page.entries.iter_mut().find(|e| e.key == key) {
+                entry.page = data_page;
+            }
+        }
+        write_page(data_file, page, &rmp_serde::to_vec(&index_page).unwrap())?;
+    }
+    Ok(())
+}
+
+//takes the keys of removed rows out of the index pages that record them
+pub fn remove_keys(
+    data_file: &mut File,
+    index: &mut KeyIndex,
+    removed: Vec<String>,
+) -> Result<(), String> {
+    let mut by_index_page: HashMap<i32, HashSet<String>> = HashMap::new();
+    for key in removed {
+        if let Some(location) = index.keys.remove(&key) {
+            by_index_page
+                .entry(location.index_page)
+                .or_default()
+                .insert(key);
+        }
+    }
+    for (page, keys) in by_index_page {
+        let mut index_page: KeyPage = read_chain_page(data_file, page)?;
+        index_page.entries.retain(|e| !keys.contains(&e.key));
+        write_page(data_file, page, &rmp_serde::to_vec(&index_page).unwrap())?;
+    }
+    Ok(())
+}
+
+//true when the page still fits after its rows were rewritten in place
+pub fn fits_page(rows: &TableRow) -> bool {
+    rmp_serde::to_vec(rows).unwrap().len() <= PAGE_SIZE
+}
@@ -1,9 +1,12 @@
-use rmp_serde::{from_slice};
+use crate::execution::Registry;
+use rmp_serde::from_slice;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::{Read};
-#[derive(Debug)]
+use std::io::Read;
+use std::path::{Path, PathBuf};
+#[derive(Debug, Clone)]
 pub enum AstNode {
     MakeRDB {
         name: String,
@@ -13,43 +16,305 @@ pub enum AstNode {
     },
     MakeTable {
         name: String,
-        columns: Vec<(String, String)>,
+        columns: Vec<Attr>,
+    },
+    MakeView {
+        name: String,
+        query: String,
     },
     Add {
         table: String,
-        values: Vec<String>,
+        rows: Vec<Vec<Assignment>>,
+        on_conflict: Option<Conflict>,
+    },
+    Remove {
+        table: String,
+        conditions: Vec<Condition>,
     },
     Pick {
         table: String,
-        columns: Vec<String>,
+        joins: Vec<Join>,
+        columns: Vec<Selective>,
+        clauses: Clauses,
     },
     ConditionalPick {
         table: String,
-        columns: Vec<String>,
-        att: Vec<String>,
-        oper: Vec<String>,
-        val: Vec<String>,
+        joins: Vec<Join>,
+        columns: Vec<Selective>,
+        conditions: Vec<Condition>,
+        clauses: Clauses,
+    },
+    Explain(Box<AstNode>), //a pick, planned but not run
+    Analyze {
+        table: Option<String>, //every table when None
     },
 }
 
+//`.join(<table> on <column> op <column>, ...)` between the picked table and `.pick`,
+//`.left join` also keeps the rows that match nothing, with nulls for the joined columns
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub table: String,
+    pub left: bool,
+    pub on: Vec<JoinCondition>,
+}
+
+#[derive(Debug, Clone)]
+pub struct JoinCondition {
+    pub left: String,
+    pub op: Operation,
+    pub right: String,
+}
+
+//the optional parts of a pick: `distinct` and the clauses that follow it, in the order
+//they are written
+#[derive(Debug, Clone, Default)]
+pub struct Clauses {
+    pub distinct: bool,
+    pub group_by: Vec<String>,
+    pub having: Vec<Condition>,
+    pub order_by: Vec<SortKey>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+//one entry of the pick list: a column (or `*`), an aggregate over a column or an
+//expression, which may be named with `as`
+#[derive(Debug, Clone)]
+pub enum Selective {
+    Column(String),
+    Aggregate {
+        func: AggregateFn,
+        column: Option<String>, //None for count(*)
+    },
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+    },
+}
+
+impl fmt::Display for Selective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selective::Column(name) => f.pad(name),
+            Selective::Aggregate { func, column } => f.pad(&format!(
+                "{}({})",
+                format!("{:?}", func).to_lowercase(),
+                column.as_deref().unwrap_or("*")
+            )),
+            Selective::Expr {
+                alias: Some(alias), ..
+            } => f.pad(alias),
+            Selective::Expr { expr, alias: None } => f.pad(&expr.to_string()),
+        }
+    }
+}
+
+//a scalar expression, an aggregate in it is a column named the way the pick list shows it
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Column(String),
+    Literal(String),
+    Binary {
+        op: ArithOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    //a comparison, only written as a function argument, that is true or false
+    Compare {
+        op: Operation,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Param(usize), //placeholder `$n` of a prepared statement, from 0
+    Value(Value), //what a bind put in place of a placeholder, it keeps its type
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Column(name) => f.pad(name),
+            Expr::Literal(value) if value.parse::<f64>().is_ok() => f.pad(value),
+            Expr::Literal(value) => f.pad(&format!("'{}'", value)),
+            Expr::Binary { op, left, right } => {
+                //nested operations keep their parentheses so the text reads back the same
+                let side = |e: &Expr| match e {
+                    Expr::Binary { .. } => format!("({})", e),
+                    _ => e.to_string(),
+                };
+                let op = match op {
+                    ArithOp::Add => "+",
+                    ArithOp::Sub => "-",
+                    ArithOp::Mul => "*",
+                    ArithOp::Div => "/",
+                    ArithOp::Rem => "%",
+                };
+                f.pad(&format!("{} {} {}", side(left), op, side(right)))
+            }
+            Expr::Call { name, args } => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                f.pad(&format!("{}({})", name, args.join(", ")))
+            }
+            Expr::Compare { op, left, right } => f.pad(&format!("{} {} {}", left, op, right)),
+            Expr::Param(index) => f.pad(&format!("${}", index + 1)),
+            Expr::Value(Value::Text(text)) => f.pad(&format!("'{}'", text)),
+            Expr::Value(value) => f.pad(&value.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFn {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+//one `order by` column or aggregate, ascending unless `desc` is given
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub column: String,
+    pub descending: bool,
+}
+
+//one value of `.add`, either positional or named with `att_name = value`
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub column: Option<String>,
+    pub value: Expr, //a literal, or a placeholder until the statement is bound
+}
+
+//what `.add(...) on conflict` does with a row whose key is already stored, `.put` replaces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conflict {
+    Replace,
+    Ignore,
+    Update,
+}
+
+//one test of a where-clause, having or check constraint, `left` is None for `exists`
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub left: Option<Expr>,
+    pub test: Test,
+}
+
+#[derive(Debug, Clone)]
+pub enum Test {
+    Compare(Operation, Expr), //left op right, a word on the right naming no column is a value
+    In(Vec<Expr>),            //left in (value, ...), literals or placeholders
+    Between(Expr, Expr),      //left between low and high, both included
+    InPick(Box<AstNode>),     //left in (<pick of one column>)
+    Exists(Box<AstNode>),     //exists (<pick>)
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let left = self
+            .left
+            .as_ref()
+            .map(|l| l.to_string())
+            .unwrap_or_default();
+        let text = match &self.test {
+            Test::Compare(op, right) => format!("{} {} {}", left, op, right),
+            Test::In(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                format!("{} in ({})", left, values.join(", "))
+            }
+            Test::Between(low, high) => format!("{} between {} and {}", left, low, high),
+            Test::InPick(_) => format!("{} in (subquery)", left),
+            Test::Exists(_) => "exists (subquery)".to_string(),
+        };
+        f.pad(&text)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TableInfo {
     pub tables: HashMap<String, i32>, //this tells the table number which can be used to find the page number (number*4096)
+    #[serde(default)]
+    pub tails: HashMap<String, i32>, //last page of each table's chain, where new rows are appended
+    #[serde(default)]
+    pub indexes: HashMap<String, i32>, //first page of the key index of tables with a key column
+    #[serde(default)]
+    pub stats: HashMap<String, i32>, //first page of the statistics of analyzed tables
+}
+
+//key index pages map every key of a table to the data page holding its row
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KeyPage {
+    pub entries: Vec<KeyEntry>,
+    pub next: i32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct KeyEntry {
+    pub key: String,
+    pub page: i32,
+}
+
+//statistics pages hold what the last analyze found in each column of a table, in schema order
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StatsPage {
+    pub columns: Vec<ColumnStats>,
+    pub next: i32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct TableRow {
     pub rows: Vec<Row>,
+    #[serde(default)]
+    pub next: i32, //next page of the same table, 0 on the last page
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Row {
     pub cells: Vec<Cell>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cell {
     pub value: String,
+    #[serde(default)]
+    pub null: bool,
+}
+
+impl Cell {
+    pub fn new(value: String) -> Self {
+        Self { value, null: false }
+    }
+
+    pub fn null() -> Self {
+        Self {
+            value: String::new(),
+            null: true,
+        }
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.null {
+            f.pad("null")
+        } else {
+            f.pad(&self.value)
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -57,28 +322,91 @@ pub struct Database {
     pub tables: i32,
     pub name: String,
     pub table_details: Vec<TableSchema>,
+    #[serde(default)]
+    pub views: Vec<ViewSchema>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TableSchema {
     pub name: String,
     pub attributes: Vec<Attr>,
+    #[serde(default)]
+    pub last_id: i32, //last value handed out to autoincrement columns
+    #[serde(default)]
+    pub rows: usize, //counted by every add, recounted by analyze
 }
 
+//what `analyze` found in a column, the planner estimates how many rows a condition keeps
+//with it. nulls are not counted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnStats {
+    pub distinct: usize,
+    pub min: Option<String>,
+    pub max: Option<String>,
+}
+
+//a saved pick that is picked from like a table, its query runs again every time
 #[derive(Debug, Serialize, Deserialize)]
+pub struct ViewSchema {
+    pub name: String,
+    pub query: String, //the text of the pick, parsed like a check when the view is read
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Attr {
     pub col_name: String,
     pub datatype: DataType,
+    #[serde(default)]
+    pub references: Option<ForeignKey>,
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub check: Option<String>, //condition source, parsed again on every insert
+    #[serde(default)]
+    pub autoincrement: bool,
+    #[serde(default)]
+    pub key: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Attr {
+    //a column without any constraint
+    pub fn new(col_name: String, datatype: DataType) -> Self {
+        Attr {
+            col_name,
+            datatype,
+            references: None,
+            default: None,
+            check: None,
+            autoincrement: false,
+            key: false,
+        }
+    }
+}
+
+//column constraint `references <table>(<col>)`, inserted values must exist in the parent column
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForeignKey {
+    pub table: String,
+    pub column: String,
+    pub on_delete: OnDelete,
+}
+
+//what happens to child rows when a remove takes their parent value away
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OnDelete {
+    Restrict,
+    Cascade,
+    SetNull,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum DataType {
     Int,
     String,
     Bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Operation {
     Equal,
     Grt,
@@ -86,26 +414,192 @@ pub enum Operation {
     GrtEq,
     LesEq,
     NotEq,
+    Like,  //`%` for any run of characters, `_` for one
+    ILike, //like, ignoring case
+    Contains,
+    StartsWith,
+    EndsWith,
+    Matches, //regular expression
+}
+
+impl Operation {
+    //the tests that read the value as a pattern instead of ordering against it
+    pub fn is_pattern(self) -> bool {
+        !matches!(
+            self,
+            Operation::Equal
+                | Operation::Grt
+                | Operation::Les
+                | Operation::GrtEq
+                | Operation::LesEq
+                | Operation::NotEq
+        )
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Operation::Equal => "==",
+            Operation::Grt => ">",
+            Operation::Les => "<",
+            Operation::GrtEq => ">=",
+            Operation::LesEq => "<=",
+            Operation::NotEq => "!=",
+            Operation::Like => "like",
+            Operation::ILike => "ilike",
+            Operation::Contains => "contains",
+            Operation::StartsWith => "starts with",
+            Operation::EndsWith => "ends with",
+            Operation::Matches => "matches",
+        })
+    }
+}
+
+//what a statement gives back. a pick has its columns and rows, any other statement a
+//message saying what it did and the number of rows it added or replaced
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueryResult {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+    pub affected: usize,
+    pub message: Option<String>, //None for a pick
+}
+
+impl QueryResult {
+    pub(crate) fn done(message: String, affected: usize) -> Self {
+        QueryResult {
+            message: Some(message),
+            affected,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn rows(
+        attributes: &[Attr],
+        rows: impl Iterator<Item = Result<Row, String>>,
+    ) -> Result<Self, String> {
+        Ok(QueryResult {
+            columns: attributes.iter().map(Column::of).collect(),
+            rows: rows
+                .map(|row| row.map(|row| Value::row(&row, attributes)))
+                .collect::<Result<_, _>>()?,
+            ..Default::default()
+        })
+    }
+}
+
+//a column of a pick's result
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub datatype: DataType,
+}
+
+impl Column {
+    pub(crate) fn of(attr: &Attr) -> Self {
+        Column {
+            name: attr.col_name.clone(),
+            datatype: attr.datatype,
+        }
+    }
+}
+
+//one value of a result row, or of a placeholder bound to a prepared statement. a value of
+//an int column that has a fraction, like an average, is a float
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bool(bool),
+    Null,
+}
+
+impl Value {
+    //reads a stored cell as the type of its column
+    pub(crate) fn of(cell: &Cell, datatype: DataType) -> Self {
+        if cell.null {
+            return Value::Null;
+        }
+        let value = &cell.value;
+        match datatype {
+            DataType::Int => value
+                .parse()
+                .map(Value::Int)
+                .or_else(|_| value.parse().map(Value::Float))
+                .unwrap_or_else(|_| Value::Text(value.clone())),
+            DataType::Bool => value
+                .parse()
+                .map(Value::Bool)
+                .unwrap_or_else(|_| Value::Text(value.clone())),
+            DataType::String => Value::Text(value.clone()),
+        }
+    }
+
+    //the cell a value is stored as, text stays text even when it reads `null`
+    pub(crate) fn cell(&self) -> Cell {
+        match self {
+            Value::Null => Cell::null(),
+            value => Cell::new(value.to_string()),
+        }
+    }
+
+    //the column type a value is, null has none
+    pub(crate) fn datatype(&self) -> Option<DataType> {
+        match self {
+            Value::Int(_) | Value::Float(_) => Some(DataType::Int),
+            Value::Text(_) => Some(DataType::String),
+            Value::Bool(_) => Some(DataType::Bool),
+            Value::Null => None,
+        }
+    }
+
+    //whether the value can go in, or be compared with, a column of the type
+    pub(crate) fn fits(&self, datatype: DataType) -> bool {
+        self.datatype().is_none_or(|own| own == datatype)
+    }
+
+    pub(crate) fn row(row: &Row, attributes: &[Attr]) -> Vec<Value> {
+        row.cells
+            .iter()
+            .zip(attributes)
+            .map(|(cell, attr)| Value::of(cell, attr.datatype))
+            .collect()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => f.pad(&n.to_string()),
+            Value::Float(x) => f.pad(&x.to_string()),
+            Value::Text(text) => f.pad(text),
+            Value::Bool(b) => f.pad(&b.to_string()),
+            Value::Null => f.pad("null"),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ActiveDataBase {
-    pub path: String,
+    pub path: PathBuf, //the data file
     pub active_db: Database,
+    pub functions: Registry, //built-ins and the functions registered while it is open
 }
 
 impl ActiveDataBase {
-    pub fn open(name: &str) -> std::io::Result<Self> {
-        let path = format!("Databases/{}.rdb", name);
-        let mut file = File::open(path)?;
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = File::open(&path)?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
 
-        let db: Database = from_slice(&buf).expect("Failed to decode DB file");
+        let db: Database = from_slice(&buf).map_err(std::io::Error::other)?;
 
         Ok(Self {
-            path: name.to_string(),
+            path: path.as_ref().to_path_buf(),
             active_db: db,
+            functions: Registry::default(),
         })
     }
 }
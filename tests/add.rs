@@ -0,0 +1,96 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+
+#[test]
+fn many_rows_spread_over_chained_pages() {
+    let db = TempDb::new("bulk");
+    let mut conn = db.open();
+    conn.execute("make table t( n : int, s : string )").unwrap();
+    let tuples: Vec<String> = (0..3000).map(|n| format!("( {}, row{} )", n, n)).collect();
+    let done = conn
+        .execute(&format!("t.add( {} )", tuples.join(", ")))
+        .unwrap();
+    assert_eq!(done.affected, 3000);
+    conn.execute("t.add( 3000, last )").unwrap();
+    drop(conn);
+
+    let mut conn = db.open();
+    assert_eq!(rows(&mut conn, "t.pick( count(*) )"), [[int(3001)]]);
+    let found = rows(&mut conn, "t.pick( s ) where ( n == 2999 )");
+    assert_eq!(found, [[text("row2999")]]);
+    let found = rows(&mut conn, "t.pick( n ) where ( s == last )");
+    assert_eq!(found, [[int(3000)]]);
+    let explained = rows(&mut conn, "explain t.pick( n )");
+    assert!(format!("{:?}", explained).contains("3001 rows"));
+}
+
+#[test]
+fn a_bad_row_keeps_the_whole_add_out() {
+    let db = TempDb::new("bulk-refused");
+    let mut conn = db.open();
+    conn.execute("make table t( n : int, s : string )").unwrap();
+    let refused = conn.execute("t.add( ( 1, a ), ( two, b ), ( 3, c ) )");
+    assert!(refused.is_err());
+    let wide = "x".repeat(5000);
+    assert!(conn.execute(&format!("t.add( 1, {} )", wide)).is_err());
+    assert!(rows(&mut conn, "t.pick( n )").is_empty());
+}
+
+#[test]
+fn conflicts_on_a_key_follow_the_chosen_action() {
+    let db = TempDb::new("upsert");
+    let mut conn = db.open();
+    conn.execute("make table stock( sku : string key, qty : int, note : string )")
+        .unwrap();
+    conn.execute("stock.add( ( a, 1, first ), ( b, 2, second ) )")
+        .unwrap();
+
+    let duplicate = conn.execute("stock.add( a, 5, again )").unwrap_err();
+    assert!(duplicate.to_string().contains("Duplicate key a"));
+    conn.execute("stock.put( ( a, 10, replaced ), ( c, 3, third ) )")
+        .unwrap();
+    conn.execute("stock.add( ( b, 20, skipped ), ( d, 4, fourth ) ) on conflict ignore")
+        .unwrap();
+    conn.execute("stock.add( sku = c, qty = 30 ) on conflict update")
+        .unwrap();
+    conn.execute("stock.add( ( d, 40, x ), ( d, 41, y ) ) on conflict replace")
+        .unwrap();
+
+    let found = rows(&mut conn, "stock.pick( sku, qty, note ) order by sku");
+    assert_eq!(
+        found,
+        [
+            [text("a"), int(10), text("replaced")],
+            [text("b"), int(2), text("second")],
+            [text("c"), int(30), text("third")],
+            [text("d"), int(41), text("y")]
+        ]
+    );
+}
+
+#[test]
+fn replaced_rows_that_outgrow_their_page_are_moved() {
+    let db = TempDb::new("upsert-moved");
+    let mut conn = db.open();
+    conn.execute("make table t( id : int key, s : string )")
+        .unwrap();
+    let tuples: Vec<String> = (0..60)
+        .map(|n| format!("( {}, {} )", n, "x".repeat(50)))
+        .collect();
+    conn.execute(&format!("t.add( {} )", tuples.join(", ")))
+        .unwrap();
+
+    let grown = "y".repeat(1500);
+    for id in [0, 1, 2] {
+        conn.execute(&format!("t.put( {}, {} )", id, grown))
+            .unwrap();
+    }
+    assert_eq!(rows(&mut conn, "t.pick( count(*) )"), [[int(60)]]);
+    for id in [0, 2, 30, 59] {
+        let found = rows(&mut conn, &format!("t.pick( id ) where ( id == {} )", id));
+        assert_eq!(found, [[int(id)]]);
+    }
+    let found = rows(&mut conn, &format!("t.pick( id ) where ( s == {} )", grown));
+    assert_eq!(found.len(), 3);
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+
+//the bytes the catalog asked for, from "The catalog needs <n> bytes ..."
+fn needed(error: &str) -> usize {
+    let rest = &error[error.find("needs ").expect(error) + 6..];
+    rest[..rest.find(' ').unwrap()].parse().unwrap()
+}
+
+#[test]
+fn a_full_catalog_is_an_error() {
+    let db = TempDb::new("catalog");
+    let mut conn = db.open();
+    conn.execute("make table words( w : string )").unwrap();
+    conn.execute("words.add( hello )").unwrap();
+
+    let mut failed = None;
+    for i in 0..400 {
+        if let Err(e) = conn.execute(&format!("make table tablenumber{}( a : int )", i)) {
+            failed = Some(e.to_string());
+            break;
+        }
+    }
+    let failed = failed.unwrap();
+    assert!(failed.contains("only has a page"), "{}", failed);
+    assert!(failed.ends_with("table not created"), "{}", failed);
+    conn.execute("words.add( again )").unwrap();
+    assert_eq!(rows(&mut conn, "words.pick( w )").len(), 2);
+}
+
+#[test]
+fn rows_are_refused_when_the_catalog_can_not_count_them() {
+    let db = TempDb::new("counter");
+    let mut conn = db.open();
+    conn.execute("make table s( id : int serial, b : string )")
+        .unwrap();
+
+    //a view long enough to overflow the catalog tells how much room is left, a second one
+    //then fills the page to the last byte
+    let view = |pad: usize| {
+        format!(
+            "make view v as s.pick( b ) where ( b == '{}' )",
+            "x".repeat(pad)
+        )
+    };
+    let error = conn.execute(&view(5000)).unwrap_err().to_string();
+    let pad = 5000 - (needed(&error) - 4096);
+    conn.execute(&view(pad)).unwrap();
+
+    //id 200 takes one byte more to save than the 0 the counter holds now
+    let error = conn
+        .execute("s.add( id = 200, b = y )")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("catalog"), "{}", error);
+    assert!(error.ends_with("no rows added"), "{}", error);
+    assert!(rows(&mut conn, "s.pick( id )").is_empty());
+    drop(conn);
+
+    //nothing was stored, so the ids handed out after reopening are still the first ones
+    let mut conn = db.open();
+    conn.execute("s.add( b = z )").unwrap();
+    assert_eq!(rows(&mut conn, "s.pick( id, b )"), [[int(1), text("z")]]);
+}
+
+#[test]
+fn statistics_of_many_wide_tables_are_saved() {
+    let db = TempDb::new("wide-stats");
+    let mut conn = db.open();
+    let columns: Vec<String> = (0..8).map(|c| format!("c{} : string", c)).collect();
+    for t in 0..9 {
+        conn.execute(&format!("make table t{}( {} )", t, columns.join(", ")))
+            .unwrap();
+        for r in 0..8 {
+            let values: Vec<String> = (0..8)
+                .map(|c| format!("'{}{}'", r % 4, format!("v{}", c).repeat(15)))
+                .collect();
+            conn.execute(&format!("t{}.add( {} )", t, values.join(", ")))
+                .unwrap();
+        }
+    }
+    //a column whose smallest and largest value do not fit a page together
+    conn.execute("make table big( s : string )").unwrap();
+    for c in ["a", "b"] {
+        conn.execute(&format!("big.add( '{}' )", c.repeat(3000)))
+            .unwrap();
+    }
+    let done = conn.execute("analyze").unwrap();
+    assert_eq!(done.message.unwrap().lines().count(), 10);
+
+    //eight rows with four distinct values keep two rows per value
+    let plan = rows(&mut conn, "explain t8.pick( c0 ) where ( c7 == 'x' )");
+    assert!(plan[1][0].to_string().contains("(~2 rows)"), "{:?}", plan);
+    drop(conn);
+
+    let mut conn = db.open();
+    let plan = rows(&mut conn, "explain t0.pick( c0 ) where ( c3 == 'x' )");
+    assert!(plan[1][0].to_string().contains("(~2 rows)"), "{:?}", plan);
+}
@@ -0,0 +1,43 @@
+//helpers shared by the test files, each uses only some of them
+#![allow(dead_code)]
+
+use runedb::{Connection, RuneDB, Value};
+use std::path::PathBuf;
+
+//a data file in the temp directory, removed again when the test ends
+pub struct TempDb(pub PathBuf);
+
+impl TempDb {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("runedb-test-{}-{}.rdb", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        TempDb(path)
+    }
+
+    pub fn open(&self) -> Connection {
+        RuneDB::open(&self.0).expect("data file does not open")
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+//every row of a pick, failing the test on any error
+pub fn rows(conn: &mut Connection, pick: &str) -> Vec<Vec<Value>> {
+    conn.query(pick)
+        .expect(pick)
+        .collect::<Result<_, _>>()
+        .expect(pick)
+}
+
+pub fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+pub fn int(value: i64) -> Value {
+    Value::Int(value)
+}
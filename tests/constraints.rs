@@ -0,0 +1,224 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+use runedb::Value;
+
+#[test]
+fn referenced_values_can_not_be_replaced_away() {
+    let db = TempDb::new("references");
+    let mut conn = db.open();
+    conn.execute("make table p( code : string key, label : string )")
+        .unwrap();
+    conn.execute("make table c( pc : string references p(code) on delete restrict )")
+        .unwrap();
+    conn.execute("p.add( a, first )").unwrap();
+    conn.execute("c.add( a )").unwrap();
+    conn.execute("p.put( a, renamed )").unwrap();
+    assert!(conn.execute("c.add( b )").is_err());
+    let found = rows(&mut conn, "p.pick( label )");
+    assert_eq!(found, [[text("renamed")]]);
+}
+
+#[test]
+fn restrict_keeps_referenced_rows() {
+    let db = TempDb::new("restrict");
+    let mut conn = db.open();
+    conn.execute("make table p( code : string key )").unwrap();
+    conn.execute("make table c( pc : string references p(code) )")
+        .unwrap();
+    conn.execute("p.add( (a), (b) )").unwrap();
+    conn.execute("c.add( a )").unwrap();
+
+    let refused = conn
+        .execute("p.remove( code != z )")
+        .unwrap_err()
+        .to_string();
+    assert!(refused.contains("still referenced by c(pc)"), "{}", refused);
+    assert!(refused.contains("no rows removed"), "{}", refused);
+    assert_eq!(rows(&mut conn, "p.pick( code )").len(), 2);
+
+    let done = conn.execute("p.remove( code == b )").unwrap();
+    assert_eq!(done.affected, 1);
+    assert_eq!(rows(&mut conn, "p.pick( code )"), [[text("a")]]);
+}
+
+#[test]
+fn cascade_removes_referencing_rows_down_the_chain() {
+    let db = TempDb::new("cascade");
+    let mut conn = db.open();
+    conn.execute("make table p( code : string key )").unwrap();
+    conn.execute("make table c( id : int key, pc : string references p(code) on delete cascade )")
+        .unwrap();
+    conn.execute("make table g( ci : int references c(id) on delete cascade )")
+        .unwrap();
+    conn.execute("p.add( (a), (b) )").unwrap();
+    conn.execute("c.add( (1, a), (2, a), (3, b) )").unwrap();
+    conn.execute("g.add( (1), (2), (3), (3) )").unwrap();
+
+    let done = conn.execute("p.remove( code == a )").unwrap();
+    assert_eq!(done.affected, 1);
+    let message = done.message.unwrap();
+    assert!(message.contains("2 removed from c"), "{}", message);
+    assert!(message.contains("2 removed from g"), "{}", message);
+    assert_eq!(rows(&mut conn, "c.pick( id )"), [[int(3)]]);
+    assert_eq!(rows(&mut conn, "g.pick( ci )"), [[int(3)], [int(3)]]);
+    assert_eq!(rows(&mut conn, "c.pick( count(*) )"), [[int(1)]]);
+}
+
+#[test]
+fn cascade_follows_a_table_referencing_itself() {
+    let db = TempDb::new("selfref");
+    let mut conn = db.open();
+    conn.execute(
+        "make table staff( id : int key, boss : int references staff(id) on delete cascade )",
+    )
+    .unwrap();
+    conn.execute("staff.add( id = 1 )").unwrap();
+    conn.execute("staff.add( (2, 1), (4, 1) )").unwrap();
+    conn.execute("staff.add( 3, 2 )").unwrap();
+    conn.execute("staff.add( id = 5 )").unwrap();
+
+    conn.execute("staff.remove( id == 2 )").unwrap();
+    assert_eq!(
+        rows(&mut conn, "staff.pick( id ) order by id"),
+        [[int(1)], [int(4)], [int(5)]]
+    );
+    conn.execute("staff.remove( id == 1 )").unwrap();
+    assert_eq!(rows(&mut conn, "staff.pick( id )"), [[int(5)]]);
+}
+
+#[test]
+fn set_null_clears_the_reference() {
+    let db = TempDb::new("setnull");
+    let mut conn = db.open();
+    conn.execute("make table p( code : string key )").unwrap();
+    conn.execute("make table c( id : int, pc : string references p(code) on delete set null )")
+        .unwrap();
+    conn.execute("p.add( (a), (b) )").unwrap();
+    conn.execute("c.add( (1, a), (2, b) )").unwrap();
+
+    let done = conn.execute("p.remove( code == a )").unwrap();
+    assert!(done.message.unwrap().contains("1 set null in c"));
+    assert_eq!(
+        rows(&mut conn, "c.pick( id, pc ) order by id"),
+        [[int(1), Value::Null], [int(2), text("b")]]
+    );
+
+    let key = conn.execute("make table k( pc : string key references p(code) on delete set null )");
+    assert!(key.unwrap_err().to_string().contains("can not be set null"));
+}
+
+#[test]
+fn removed_keys_leave_the_key_index() {
+    let db = TempDb::new("removekeys");
+    let mut conn = db.open();
+    conn.execute("make table t( id : int key, s : string )")
+        .unwrap();
+    for i in 0..200 {
+        conn.execute(&format!("t.add( {}, value{} )", i, i))
+            .unwrap();
+    }
+
+    let done = conn.execute("t.remove( id >= 50, id < 150 )").unwrap();
+    assert_eq!(done.affected, 100);
+    assert!(rows(&mut conn, "t.pick( s ) where ( id == 70 )").is_empty());
+    assert_eq!(
+        rows(&mut conn, "t.pick( s ) where ( id == 170 )"),
+        [[text("value170")]]
+    );
+    conn.execute("t.add( 70, again )").unwrap();
+    assert_eq!(
+        rows(&mut conn, "t.pick( s ) where ( id == 70 )"),
+        [[text("again")]]
+    );
+    assert_eq!(rows(&mut conn, "t.pick( count(*) )"), [[int(101)]]);
+
+    let removing = conn.prepare("t.remove( id == ? )").unwrap();
+    assert_eq!(
+        removing.execute(&mut conn, &[int(170)]).unwrap().affected,
+        1
+    );
+}
+
+#[test]
+fn negative_numbers_reach_checks_defaults_and_conditions() {
+    let db = TempDb::new("negative");
+    let mut conn = db.open();
+    conn.execute("make table t( id : int, age : int check ( age >= 0 ), shift : int default -1 )")
+        .unwrap();
+    let refused = conn.execute("t.add( id = 1, age = -5 )").unwrap_err();
+    assert!(
+        refused.to_string().contains("Check (age >= 0 ) failed"),
+        "{}",
+        refused
+    );
+    conn.execute("t.add( id = -2, age = 4 )").unwrap();
+    conn.execute("t.add( 3, 7, -9 )").unwrap();
+
+    assert_eq!(
+        rows(&mut conn, "t.pick( id, shift ) order by id"),
+        [[int(-2), int(-1)], [int(3), int(-9)]]
+    );
+    assert_eq!(
+        rows(&mut conn, "t.pick( id ) where ( shift in (-1, 2) )"),
+        [[int(-2)]]
+    );
+    assert_eq!(
+        rows(&mut conn, "t.pick( id ) where ( shift between -10 and -5 )"),
+        [[int(3)]]
+    );
+}
+
+#[test]
+fn defaults_fill_in_and_checks_guard_every_row() {
+    let db = TempDb::new("checks");
+    let mut conn = db.open();
+    conn.execute(
+        "make table people( name : string default anon, age : int default 18 check ( age >= 0, age < 150 ) )",
+    )
+    .unwrap();
+    conn.execute("people.add( age = 30 )").unwrap();
+    conn.execute("people.add( name = bo )").unwrap();
+
+    let refused = conn.execute("people.add( ( cy, 40 ), ( di, 200 ) )");
+    assert!(refused.unwrap_err().to_string().contains("no rows added"));
+    assert!(conn.execute("people.add( name = ed, age = x )").is_err());
+    assert!(conn.execute("make table bad( a : int default x )").is_err());
+    assert!(
+        conn.execute("make table bad( a : int check ( b > 1 ) )")
+            .is_err()
+    );
+
+    let found = rows(&mut conn, "people.pick( name, age ) order by age");
+    assert_eq!(found, [[text("bo"), int(18)], [text("anon"), int(30)]]);
+}
+
+#[test]
+fn serial_ids_count_on_after_reopening() {
+    let db = TempDb::new("serial");
+    let mut conn = db.open();
+    conn.execute("make table items( id : int serial, name : string )")
+        .unwrap();
+    conn.execute("items.add( name = lamp )").unwrap();
+    conn.execute("items.add( ( desk ), ( chair ) )").unwrap();
+    conn.execute("items.add( id = 10, name = shelf )").unwrap();
+    drop(conn);
+
+    let mut conn = db.open();
+    conn.execute("items.add( name = rug )").unwrap();
+    assert!(
+        conn.execute("make table bad( id : string autoincrement )")
+            .is_err()
+    );
+    let found = rows(&mut conn, "items.pick( id, name ) order by id");
+    assert_eq!(
+        found,
+        [
+            [int(1), text("lamp")],
+            [int(2), text("desk")],
+            [int(3), text("chair")],
+            [int(10), text("shelf")],
+            [int(11), text("rug")]
+        ]
+    );
+}
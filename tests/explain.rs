@@ -0,0 +1,114 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+use runedb::Connection;
+
+fn shop(db: &TempDb) -> Connection {
+    let mut conn = db.open();
+    conn.execute("make table p( id : int key, name : string )")
+        .unwrap();
+    conn.execute("make table o( n : int key, pid : int, amount : int )")
+        .unwrap();
+    conn.execute("p.add( ( 1, ann ), ( 2, bob ) )").unwrap();
+    conn.execute("o.add( ( 1, 1, 5 ), ( 2, 1, 7 ), ( 3, 2, 9 ) )")
+        .unwrap();
+    conn
+}
+
+fn plan(conn: &mut Connection, pick: &str) -> Vec<String> {
+    rows(conn, &format!("explain {}", pick))
+        .into_iter()
+        .map(|row| row[0].to_string())
+        .collect()
+}
+
+#[test]
+fn keys_named_by_the_where_clause_are_looked_up() {
+    let db = TempDb::new("explain-keys");
+    let mut conn = shop(&db);
+    let pick = "p.pick( name ) where ( id == 2 )";
+    assert_eq!(
+        plan(&mut conn, pick),
+        [
+            "Project name  (~1 rows)",
+            "  Filter id == 2  (~1 rows)",
+            "    Key lookup p (2)  (~1 rows)",
+        ]
+    );
+    assert_eq!(rows(&mut conn, pick), [[text("bob")]]);
+
+    //more keys than the table has rows are read faster by scanning it
+    let pick = "p.pick( name ) where ( id in (1, 2, 3) )";
+    assert_eq!(plan(&mut conn, pick)[2], "    Scan p  (~2 rows)");
+    assert_eq!(rows(&mut conn, pick), [[text("ann")], [text("bob")]]);
+}
+
+#[test]
+fn every_step_of_a_pick_is_explained_in_the_order_it_runs() {
+    let db = TempDb::new("explain-steps");
+    let mut conn = shop(&db);
+    let pick = "p.join( o on id == pid ).pick( name, sum(amount) as total ) group by name \
+                having ( sum(amount) > 1 ) order by total desc limit 1";
+    assert_eq!(
+        plan(&mut conn, pick),
+        [
+            "Limit 1 offset 0  (~1 rows)",
+            "  Project name, total  (~1 rows)",
+            "    Sort total desc  (~1 rows)",
+            "      Compute sum(amount) as total  (~1 rows)",
+            "        Filter sum(amount) > 1  (~1 rows)",
+            "          Aggregate by name  (~1 rows)",
+            "            Join hash on id == pid  (~3 rows)",
+            "              Scan p  (~2 rows)",
+            "              Scan o  (~3 rows)",
+        ]
+    );
+    assert_eq!(rows(&mut conn, pick), [[text("ann"), int(12)]]);
+
+    let pick = "p.join( o on id < pid ).pick( distinct name ) offset 1";
+    assert_eq!(
+        plan(&mut conn, pick),
+        [
+            "Offset 1  (~1 rows)",
+            "  Project distinct name  (~2 rows)",
+            "    Join nested loop on id < pid  (~2 rows)",
+            "      Scan p  (~2 rows)",
+            "      Scan o  (~3 rows)",
+        ]
+    );
+}
+
+#[test]
+fn an_exists_that_found_nothing_keeps_every_row_out() {
+    let db = TempDb::new("explain-exists");
+    let mut conn = shop(&db);
+    let pick = "p.pick( name ) where ( exists ( o.pick( n ) where ( n == 99 ) ) )";
+    assert_eq!(
+        plan(&mut conn, pick),
+        [
+            "Project name  (~0 rows)",
+            "  Filter exists (subquery)  (~0 rows)",
+            "    Scan p  (~2 rows)",
+        ]
+    );
+    assert!(rows(&mut conn, pick).is_empty());
+
+    //one that found a row is no step at all
+    let pick = "p.pick( name ) where ( exists ( o.pick( n ) where ( n == 3 ) ) )";
+    assert_eq!(plan(&mut conn, pick).len(), 2);
+    assert_eq!(rows(&mut conn, pick).len(), 2);
+}
+
+#[test]
+fn tables_may_start_with_a_keyword() {
+    let db = TempDb::new("keywords");
+    let mut conn = db.open();
+    for table in ["explainx", "analyzer"] {
+        conn.execute(&format!("make table {}( a : int )", table))
+            .unwrap();
+        conn.execute(&format!("{}.add( 1 )", table)).unwrap();
+        let found = rows(&mut conn, &format!("{}.pick( a )", table));
+        assert_eq!(found, [[int(1)]]);
+    }
+    assert_eq!(plan(&mut conn, "explainx.pick( a )").len(), 2);
+}
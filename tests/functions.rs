@@ -0,0 +1,112 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+use runedb::{DataType, Value};
+
+#[test]
+fn registered_functions_get_values() {
+    let db = TempDb::new("functions");
+    let mut conn = db.open();
+    conn.register_function(
+        "shout",
+        &[DataType::String],
+        DataType::String,
+        |args| match &args[0] {
+            Value::Text(s) => Value::Text(s.to_uppercase()),
+            _ => text("nothing"),
+        },
+    )
+    .unwrap();
+    conn.execute("make table t( id : int key, s : string )")
+        .unwrap();
+    conn.execute("t.add( 1, hi )").unwrap();
+    conn.execute("t.add( id = 2 )").unwrap();
+    let found = rows(&mut conn, "t.pick( shout(s) as loud ) order by id");
+    assert_eq!(found, [[text("HI")], [text("nothing")]]);
+}
+
+#[test]
+fn a_result_of_the_wrong_type_is_null() {
+    let db = TempDb::new("function-types");
+    let mut conn = db.open();
+    conn.register_function(
+        "size",
+        &[DataType::String],
+        DataType::Int,
+        |args| match &args[0] {
+            Value::Text(s) if !s.is_empty() => int(s.len() as i64),
+            _ => text("empty"),
+        },
+    )
+    .unwrap();
+    conn.execute("make table t( id : int, s : string )")
+        .unwrap();
+    conn.execute("t.add( ( 1, abc ), ( 2, '' ) )").unwrap();
+    let found = rows(&mut conn, "t.pick( id, size(s) + 1 as n ) order by id");
+    assert_eq!(found, [[int(1), int(4)], [int(2), Value::Null]]);
+    let found = rows(&mut conn, "t.pick( id ) where ( size(s) > 0 )");
+    assert_eq!(found, [[int(1)]]);
+}
+
+#[test]
+fn built_in_functions_work_on_each_row() {
+    let db = TempDb::new("builtins");
+    let mut conn = db.open();
+    conn.execute("make table t( s : string, n : int, d : string )")
+        .unwrap();
+    conn.execute("t.add( ( '  Hello ', -7, '2024-02-28' ), ( null, 12, nope ) )")
+        .unwrap();
+
+    let found = rows(
+        &mut conn,
+        "t.pick( upper(trim(s)), length(s), substr(trim(s), 2, 3), concat(s, n), abs(n), mod(n, 5), coalesce(s, 'none') ) order by n",
+    );
+    assert_eq!(
+        found,
+        [
+            [
+                text("HELLO"),
+                int(8),
+                text("ell"),
+                text("  Hello -7"),
+                int(7),
+                int(-2),
+                text("  Hello ")
+            ],
+            [
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                text("12"),
+                int(12),
+                int(2),
+                text("none")
+            ]
+        ]
+    );
+    let found = rows(
+        &mut conn,
+        "t.pick( if(n > 0, 'up', 'down'), year(d), date_add(d, 2), days_between(d, '2024-03-01') ) order by n",
+    );
+    assert_eq!(
+        found,
+        [
+            [text("down"), int(2024), text("2024-03-01"), int(2)],
+            [text("up"), Value::Null, Value::Null, Value::Null]
+        ]
+    );
+    assert!(conn.execute("t.pick( upper(n) )").is_err());
+    assert!(conn.execute("t.pick( nothing(s) )").is_err());
+}
+
+#[test]
+fn dates_out_of_range_are_null() {
+    let db = TempDb::new("dates");
+    let mut conn = db.open();
+    conn.execute("make table days( d : string, n : int )")
+        .unwrap();
+    conn.execute("days.add( ( '2024-02-28', 1 ), ( '2024-02-28', 2147483647 ) )")
+        .unwrap();
+    let found = rows(&mut conn, "days.pick( date_add(d, n) as later )");
+    assert_eq!(found, [[text("2024-02-29")], [Value::Null]]);
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+use runedb::Value;
+
+fn shop() -> (TempDb, runedb::Connection) {
+    let db = TempDb::new(&format!("join-{:?}", std::thread::current().id()));
+    let mut conn = db.open();
+    conn.execute("make table people( id : int, name : string )")
+        .unwrap();
+    conn.execute("make table orders( who : int, item : string, qty : int )")
+        .unwrap();
+    conn.execute("people.add( ( 1, ann ), ( 2, bob ), ( 3, cy ), ( null, nobody ) )")
+        .unwrap();
+    conn.execute(
+        "orders.add( ( 1, lamp, 2 ), ( 1, desk, 1 ), ( 3, rug, 5 ), ( 4, sofa, 1 ), ( null, box, 1 ) )",
+    )
+    .unwrap();
+    (db, conn)
+}
+
+#[test]
+fn hash_and_nested_loop_joins_find_the_same_rows() {
+    let (_db, mut conn) = shop();
+    let hashed = "people.join( orders on people.id == orders.who ).pick( people.name, orders.item ) order by orders.item";
+    let looped = "people.join( orders on people.id <= orders.who, people.id >= orders.who ).pick( people.name, orders.item ) order by orders.item";
+    let expected = [
+        [text("ann"), text("desk")],
+        [text("ann"), text("lamp")],
+        [text("cy"), text("rug")],
+    ];
+    assert_eq!(rows(&mut conn, hashed), expected);
+    assert_eq!(rows(&mut conn, looped), expected);
+
+    let explained = format!("{:?}", rows(&mut conn, &format!("explain {}", hashed)));
+    assert!(explained.contains("Join hash on"), "{}", explained);
+    let explained = format!("{:?}", rows(&mut conn, &format!("explain {}", looped)));
+    assert!(explained.contains("Join nested loop on"), "{}", explained);
+}
+
+#[test]
+fn a_left_join_keeps_rows_without_a_match() {
+    let (_db, mut conn) = shop();
+    let found = rows(
+        &mut conn,
+        "people.left join( orders on people.id == orders.who ).pick( people.name, orders.qty ) where ( people.id != 1 ) order by people.name",
+    );
+    assert_eq!(found, [[text("bob"), Value::Null], [text("cy"), int(5)]]);
+    let found = rows(
+        &mut conn,
+        "people.left join( orders on people.id < orders.who ).pick( count(*) )",
+    );
+    //ann and bob each match 3 and 4, cy matches 4, nobody is kept without a match
+    assert_eq!(found, [[int(6)]]);
+}
+
+#[test]
+fn joins_chain_and_check_their_columns() {
+    let (_db, mut conn) = shop();
+    conn.execute("make table items( label : string, price : int )")
+        .unwrap();
+    conn.execute("items.add( ( lamp, 30 ), ( rug, 100 ), ( desk, 80 ) )")
+        .unwrap();
+    let found = rows(
+        &mut conn,
+        "people.join( orders on people.id == orders.who ).join( items on orders.item == items.label ).pick( people.name, sum(items.price) ) group by people.name order by people.name",
+    );
+    assert_eq!(found, [[text("ann"), int(110)], [text("cy"), int(100)]]);
+    assert!(
+        conn.execute("people.join( orders on people.id == orders.nope ).pick( people.name )")
+            .is_err()
+    );
+    assert!(
+        conn.execute("people.join( orders on people.id == people.id ).pick( people.name )")
+            .is_err()
+    );
+}
+
+#[test]
+fn subqueries_pick_the_values_a_condition_tests() {
+    let (_db, mut conn) = shop();
+    let found = rows(
+        &mut conn,
+        "people.pick( name ) where ( id in ( orders.pick( who ) where ( qty > 1 ) ) ) order by name",
+    );
+    assert_eq!(found, [[text("ann")], [text("cy")]]);
+    let found = rows(
+        &mut conn,
+        "people.pick( name ) where ( id in ( orders.pick( who ) ), exists ( orders.pick( item ) where ( item == rug ) ) ) order by name",
+    );
+    assert_eq!(found, [[text("ann")], [text("cy")]]);
+    let found = rows(
+        &mut conn,
+        "people.pick( name ) where ( exists ( orders.pick( item ) where ( qty > 10 ) ) )",
+    );
+    assert!(found.is_empty());
+
+    assert!(
+        conn.execute("people.pick( name ) where ( id in ( orders.pick( who, qty ) ) )")
+            .is_err()
+    );
+    assert!(
+        conn.execute("people.pick( name ) where ( id in ( orders.pick( nope ) ) )")
+            .is_err()
+    );
+}
@@ -0,0 +1,293 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+use runedb::Value;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+
+#[test]
+fn order_by_sorts_on_each_key_in_turn() {
+    let db = TempDb::new("order-by");
+    let mut conn = db.open();
+    conn.execute("make table people( name : string, team : string, age : int )")
+        .unwrap();
+    conn.execute(
+        "people.add( ( ann, red, 9 ), ( bob, blue, 30 ), ( cat, red, 100 ), ( dan, blue, 30 ) )",
+    )
+    .unwrap();
+
+    //ints by value, not as text
+    let found = rows(&mut conn, "people.pick( name ) order by age");
+    assert_eq!(
+        found,
+        [[text("ann")], [text("bob")], [text("dan")], [text("cat")]]
+    );
+
+    //a column that is not picked still orders the rows
+    let found = rows(
+        &mut conn,
+        "people.pick( name ) order by team desc, age desc, name",
+    );
+    assert_eq!(
+        found,
+        [[text("cat")], [text("ann")], [text("bob")], [text("dan")]]
+    );
+}
+
+#[test]
+fn rows_wider_than_a_page_can_be_sorted() {
+    let db = TempDb::new("wide-sort");
+    let mut conn = db.open();
+    conn.execute("make table s( n : int, t : string )").unwrap();
+    for n in 0..300 {
+        let t = format!("{:03}", (n * 7) % 300).repeat(1000);
+        conn.execute(&format!("s.add( {}, '{}' )", n, t)).unwrap();
+    }
+
+    //every computed row is about 6000 characters, and all of them are more than the sort
+    //keeps in memory
+    let found = rows(&mut conn, "s.pick( n, concat(t, t) as d ) order by d");
+    assert_eq!(found.len(), 300);
+    for (position, row) in found.iter().enumerate() {
+        let expected = format!("{:03}", position).repeat(2000);
+        assert_eq!(row[1], text(&expected));
+        assert_eq!(row[0], int((position as i64 * 43) % 300));
+    }
+}
+
+#[test]
+fn distinct_drops_repeated_rows() {
+    let db = TempDb::new("distinct");
+    let mut conn = db.open();
+    conn.execute("make table t( team : string, age : int, distinctive : string )")
+        .unwrap();
+    conn.execute("t.add( ( red, 9, x ), ( blue, 30, y ), ( red, 9, z ), ( red, 10, x ) )")
+        .unwrap();
+
+    let found = rows(&mut conn, "t.pick( distinct team ) order by team");
+    assert_eq!(found, [[text("blue")], [text("red")]]);
+    let found = rows(&mut conn, "t.pick( distinct team, age ) order by age");
+    assert_eq!(
+        found,
+        [
+            [text("red"), int(9)],
+            [text("red"), int(10)],
+            [text("blue"), int(30)]
+        ]
+    );
+
+    //a column that only starts with the word is picked as a column
+    let found = rows(&mut conn, "t.pick( distinctive ) where ( age == 30 )");
+    assert_eq!(found, [[text("y")]]);
+    let found = rows(
+        &mut conn,
+        "t.pick( distinct distinctive ) order by distinctive",
+    );
+    assert_eq!(found, [[text("x")], [text("y")], [text("z")]]);
+}
+
+#[test]
+fn limit_and_offset_page_through_the_rows() {
+    let db = TempDb::new("limit");
+    let mut conn = db.open();
+    conn.execute("make table t( n : int )").unwrap();
+    let tuples: Vec<String> = (0..2000).map(|n| format!("( {} )", n)).collect();
+    conn.execute(&format!("t.add( {} )", tuples.join(", ")))
+        .unwrap();
+
+    let found = rows(&mut conn, "t.pick( n ) order by n desc limit 2 offset 3");
+    assert_eq!(found, [[int(1996)], [int(1995)]]);
+    assert!(rows(&mut conn, "t.pick( n ) limit 0").is_empty());
+    assert!(rows(&mut conn, "t.pick( n ) offset 5000").is_empty());
+    drop(conn);
+
+    //the last page of the table is never read when the first rows are enough
+    let length = std::fs::metadata(&db.0).unwrap().len();
+    let mut file = OpenOptions::new().write(true).open(&db.0).unwrap();
+    file.seek(SeekFrom::Start(length - 4096)).unwrap();
+    file.write_all(&[0xc1; 16]).unwrap();
+    drop(file);
+
+    let mut conn = db.open();
+    let found = rows(&mut conn, "t.pick( n ) where ( n >= 10 ) limit 3 offset 1");
+    assert_eq!(found, [[int(11)], [int(12)], [int(13)]]);
+    assert!(conn.execute("t.pick( n )").is_err());
+}
+
+#[test]
+fn aggregates_summarise_the_picked_rows() {
+    let db = TempDb::new("aggregates");
+    let mut conn = db.open();
+    conn.execute("make table t( name : string, age : int )")
+        .unwrap();
+    assert_eq!(
+        rows(&mut conn, "t.pick( count(*), sum(age), max(age) )"),
+        [[int(0), Value::Null, Value::Null]]
+    );
+    conn.execute("t.add( ( ann, 30 ), ( bob, 45 ), ( cy, null ), ( di, 20 ) )")
+        .unwrap();
+
+    let found = rows(
+        &mut conn,
+        "t.pick( count(*), count(age), sum(age), min(age), max(age), avg(age) )",
+    );
+    assert_eq!(
+        found,
+        [[
+            int(4),
+            int(3),
+            int(95),
+            int(20),
+            int(45),
+            Value::Float(95.0 / 3.0)
+        ]]
+    );
+    let found = rows(
+        &mut conn,
+        "t.pick( count(*), min(name) ) where ( age > 25 )",
+    );
+    assert_eq!(found, [[int(2), text("ann")]]);
+    assert!(conn.execute("t.pick( sum(name) )").is_err());
+}
+
+#[test]
+fn malformed_statements_are_errors() {
+    let db = TempDb::new("parse");
+    let mut conn = db.open();
+    conn.execute("make table t( a : int )").unwrap();
+    assert!(conn.execute("t.pick( a ) where ( a = 1 )").is_err());
+    assert!(conn.execute("t.pick( a ) limt 1").is_err());
+    assert!(conn.execute("t.pick( count(a )").is_err());
+    assert!(conn.execute("t.pick( a ) limit 1").is_ok());
+}
+
+#[test]
+fn groups_are_summarised_and_filtered_by_having() {
+    let db = TempDb::new("group-by");
+    let mut conn = db.open();
+    conn.execute("make table sales( team : string, region : string, amount : int )")
+        .unwrap();
+    conn.execute(
+        "sales.add( ( red, north, 5 ), ( blue, north, 7 ), ( red, south, 3 ), ( red, north, 2 ), ( green, south, 1 ) )",
+    )
+    .unwrap();
+
+    let found = rows(
+        &mut conn,
+        "sales.pick( team, count(*), sum(amount) as total ) group by team order by team",
+    );
+    assert_eq!(
+        found,
+        [
+            [text("blue"), int(1), int(7)],
+            [text("green"), int(1), int(1)],
+            [text("red"), int(3), int(10)]
+        ]
+    );
+    let found = rows(
+        &mut conn,
+        "sales.pick( team, region, max(amount) ) group by team, region having ( count(*) > 1 )",
+    );
+    assert_eq!(found, [[text("red"), text("north"), int(5)]]);
+    let found = rows(
+        &mut conn,
+        "sales.pick( region ) where ( amount > 1 ) group by region having ( sum(amount) > 2 ) order by region desc",
+    );
+    assert_eq!(found, [[text("south")], [text("north")]]);
+    assert!(
+        conn.execute("sales.pick( team, amount ) group by team")
+            .is_err()
+    );
+}
+
+#[test]
+fn string_comparisons_agree_with_ordering() {
+    let db = TempDb::new("strings");
+    let mut conn = db.open();
+    conn.execute("make table codes( c : string, n : int )")
+        .unwrap();
+    conn.execute("codes.add( ( '10', 10 ), ( '9', 9 ), ( '007', 7 ) )")
+        .unwrap();
+
+    let found = rows(&mut conn, "codes.pick( c ) where ( c == '7' )");
+    assert!(found.is_empty());
+    let found = rows(&mut conn, "codes.pick( c ) where ( n == '007' )");
+    assert_eq!(found, [[text("007")]]);
+
+    let ordered = rows(&mut conn, "codes.pick( c ) order by c");
+    assert_eq!(ordered, [[text("007")], [text("10")], [text("9")]]);
+    let below = rows(&mut conn, "codes.pick( c ) where ( c < '9' )");
+    assert_eq!(below, [[text("10")], [text("007")]]);
+    let bounds = rows(&mut conn, "codes.pick( min(c), max(c) )");
+    assert_eq!(bounds, [[text("007"), text("9")]]);
+}
+
+#[test]
+fn text_patterns_pick_matching_rows() {
+    let db = TempDb::new("patterns");
+    let mut conn = db.open();
+    conn.execute("make table files( name : string )").unwrap();
+    conn.execute("files.add( ( 'Report.txt' ), ( 'notes.md' ), ( 'report_2.txt' ), ( null ) )")
+        .unwrap();
+    let pick = |conn: &mut runedb::Connection, test: &str| {
+        rows(
+            conn,
+            &format!("files.pick( name ) where ( name {} ) order by name", test),
+        )
+    };
+
+    assert_eq!(
+        pick(&mut conn, "like '%.txt'"),
+        [[text("Report.txt")], [text("report_2.txt")]]
+    );
+    assert_eq!(pick(&mut conn, "like 'report%'"), [[text("report_2.txt")]]);
+    assert_eq!(pick(&mut conn, "ilike 'REPORT.%'"), [[text("Report.txt")]]);
+    assert_eq!(pick(&mut conn, "starts with 'no'"), [[text("notes.md")]]);
+    assert_eq!(pick(&mut conn, "ends with '.md'"), [[text("notes.md")]]);
+    assert_eq!(pick(&mut conn, "contains 't_2'"), [[text("report_2.txt")]]);
+    assert_eq!(
+        pick(&mut conn, "matches '^[a-z]+_[0-9]'"),
+        [[text("report_2.txt")]]
+    );
+    assert!(
+        conn.execute("files.pick( name ) where ( name matches '(' )")
+            .is_err()
+    );
+}
+
+#[test]
+fn expressions_compute_columns_and_test_rows() {
+    let db = TempDb::new("expressions");
+    let mut conn = db.open();
+    conn.execute(
+        "make table lines( item : string, price : int, qty : int, start : int, stop : int )",
+    )
+    .unwrap();
+    conn.execute(
+        "lines.add( ( lamp, 30, 2, 1, 5 ), ( desk, 80, 1, 7, 3 ), ( rug, 100, 0, 2, 2 ) )",
+    )
+    .unwrap();
+
+    let found = rows(
+        &mut conn,
+        "lines.pick( item, price * qty as total, price + qty * 2 - 1, price / qty, price % 7 ) order by item",
+    );
+    assert_eq!(
+        found,
+        [
+            [text("desk"), int(80), int(81), int(80), int(3)],
+            [text("lamp"), int(60), int(33), int(15), int(2)],
+            [text("rug"), int(0), int(99), Value::Null, int(2)]
+        ]
+    );
+    let found = rows(
+        &mut conn,
+        "lines.pick( item ) where ( start < stop, ( qty + 1 ) * 10 > 25 )",
+    );
+    assert_eq!(found, [[text("lamp")]]);
+    assert!(conn.execute("lines.pick( item * 2 )").is_err());
+    assert!(
+        conn.execute("lines.pick( item ) where ( nope + 1 > 2 )")
+            .is_err()
+    );
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+use runedb::Value;
+
+#[test]
+fn bound_values_keep_their_type() {
+    let db = TempDb::new("binds");
+    let mut conn = db.open();
+    conn.execute("make table notes( id : int key, body : string )")
+        .unwrap();
+    let add = conn.prepare("notes.add( ?, ? )").unwrap();
+    add.execute(&mut conn, &[int(1), text("null")]).unwrap();
+    add.execute(&mut conn, &[int(2), Value::Null]).unwrap();
+    let error = add
+        .execute(&mut conn, &[text("three"), text("x")])
+        .unwrap_err();
+    assert!(error.to_string().contains("expects"));
+
+    let find = conn
+        .prepare("notes.pick( id ) where ( body == ? )")
+        .unwrap();
+    let found: Vec<_> = find.query(&mut conn, &[text("null")]).unwrap().collect();
+    assert_eq!(found, [Ok(vec![int(1)])]);
+    let found: Vec<_> = find.query(&mut conn, &[Value::Null]).unwrap().collect();
+    assert!(found.is_empty());
+}
+
+#[test]
+fn bound_text_needs_no_quoting() {
+    let db = TempDb::new("binds-text");
+    let mut conn = db.open();
+    conn.execute("make table notes( id : int, body : string )")
+        .unwrap();
+    let add = conn.prepare("notes.add( id = $2, body = $1 )").unwrap();
+    for (id, body) in [(1, "it's \"quoted\""), (2, "a, b ) where ( c")] {
+        add.execute(&mut conn, &[text(body), int(id)]).unwrap();
+    }
+    let pick = conn
+        .prepare("notes.pick( body ) where ( id in ( $1, $2 ), body != $3 ) order by id")
+        .unwrap();
+    let found: Vec<_> = pick
+        .query(&mut conn, &[int(2), int(1), text("x")])
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    assert_eq!(
+        found,
+        [[text("it's \"quoted\"")], [text("a, b ) where ( c")]]
+    );
+    assert_eq!(rows(&mut conn, "notes.pick( count(*) )"), [[int(2)]]);
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::{TempDb, int, text};
+use runedb::{Column, DataType, Value};
+
+fn column(name: &str, datatype: DataType) -> Column {
+    Column {
+        name: name.to_string(),
+        datatype,
+    }
+}
+
+#[test]
+fn picks_describe_their_columns_and_typed_rows() {
+    let db = TempDb::new("results");
+    let mut conn = db.open();
+    let made = conn
+        .execute("make table items( id : int, name : string, sold : bool )")
+        .unwrap();
+    assert!(made.columns.is_empty() && made.rows.is_empty());
+    assert!(made.message.is_some());
+
+    let added = conn
+        .execute("items.add( ( 1, lamp, true ), ( 2, desk, null ) )")
+        .unwrap();
+    assert_eq!(added.affected, 2);
+    assert!(added.message.is_some());
+
+    let picked = conn
+        .execute("items.pick( name, sold, id * 10 as tens, count(*) ) group by name, sold, id order by id")
+        .unwrap();
+    assert_eq!(picked.message, None);
+    assert_eq!(
+        picked.columns,
+        [
+            column("name", DataType::String),
+            column("sold", DataType::Bool),
+            column("tens", DataType::Int),
+            column("count(*)", DataType::Int)
+        ]
+    );
+    assert_eq!(
+        picked.rows,
+        [
+            [text("lamp"), Value::Bool(true), int(10), int(1)],
+            [text("desk"), Value::Null, int(20), int(1)]
+        ]
+    );
+
+    let streamed = conn.query("items.pick( * )").unwrap();
+    assert_eq!(
+        streamed.columns(),
+        [
+            column("id", DataType::Int),
+            column("name", DataType::String),
+            column("sold", DataType::Bool)
+        ]
+    );
+    assert_eq!(streamed.count(), 2);
+    assert!(conn.returns_rows("explain items.pick( id )"));
+    assert!(!conn.returns_rows("items.add( 3, rug, false )"));
+}
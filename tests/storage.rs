@@ -0,0 +1,95 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+use runedb::RuneDB;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+
+#[test]
+fn rows_survive_reopening_the_file() {
+    let db = TempDb::new("reopen");
+    let mut conn = db.open();
+    conn.execute("make table items( id : int key, name : string )")
+        .unwrap();
+    conn.execute("items.add( ( 1, lamp ), ( 2, desk ) )")
+        .unwrap();
+    drop(conn);
+
+    let mut conn = db.open();
+    let found = rows(&mut conn, "items.pick( name ) where ( id == 2 )");
+    assert_eq!(found, [[text("desk")]]);
+}
+
+#[test]
+fn a_broken_catalog_is_an_error() {
+    let db = TempDb::new("broken");
+    let mut conn = db.open();
+    conn.execute("make table t( a : int )").unwrap();
+    drop(conn);
+    std::fs::write(&db.0, b"not a database").unwrap();
+    let error = RuneDB::open(&db.0).err().unwrap();
+    assert!(error.to_string().contains("Problem opening the data file"));
+}
+
+#[test]
+fn a_removed_data_file_is_an_error() {
+    let db = TempDb::new("removed");
+    let mut conn = db.open();
+    conn.execute("make table t( a : int )").unwrap();
+    std::fs::remove_file(&db.0).unwrap();
+    assert!(conn.execute("t.add( 1 )").is_err());
+    assert!(conn.query("t.pick( a )").is_err());
+}
+
+#[test]
+fn a_broken_row_page_is_an_error_row() {
+    let db = TempDb::new("broken-page");
+    let mut conn = db.open();
+    conn.execute("make table t( a : int )").unwrap();
+    conn.execute("t.add( ( 1 ), ( 2 ) )").unwrap();
+    drop(conn);
+
+    //page 2 is the first page of the only table, 0xc1 is never valid msgpack
+    let mut file = OpenOptions::new().write(true).open(&db.0).unwrap();
+    file.seek(SeekFrom::Start(2 * 4096)).unwrap();
+    file.write_all(&[0xc1; 16]).unwrap();
+    drop(file);
+
+    let mut conn = db.open();
+    let mut found = conn.query("t.pick( a )").unwrap();
+    let error = found.next().unwrap().unwrap_err();
+    assert!(error.to_string().contains("does not decode"), "{}", error);
+    assert!(found.next().is_none());
+    drop(found);
+    assert!(conn.execute("t.pick( a )").is_err());
+    assert!(conn.execute("t.add( 3 )").is_err());
+}
+
+#[test]
+fn rows_stream_out_before_later_pages_are_read() {
+    let db = TempDb::new("stream");
+    let mut conn = db.open();
+    conn.execute("make table t( n : int )").unwrap();
+    let tuples: Vec<String> = (0..2000).map(|n| format!("( {} )", n)).collect();
+    conn.execute(&format!("t.add( {} )", tuples.join(", ")))
+        .unwrap();
+    drop(conn);
+
+    //breaks the last page of the table
+    let length = std::fs::metadata(&db.0).unwrap().len();
+    let mut file = OpenOptions::new().write(true).open(&db.0).unwrap();
+    file.seek(SeekFrom::Start(length - 4096)).unwrap();
+    file.write_all(&[0xc1; 16]).unwrap();
+    drop(file);
+
+    let mut conn = db.open();
+    let found = conn.query("t.pick( n * 2 ) where ( n != 1 )").unwrap();
+    let first: Vec<_> = found.take(3).map(|row| row.unwrap()).collect();
+    assert_eq!(first, [[int(0)], [int(4)], [int(6)]]);
+
+    let found = conn.query("t.pick( n )").unwrap();
+    let read: Vec<_> = found.collect();
+    let good = read.iter().take_while(|row| row.is_ok()).count();
+    assert!(good > 0 && good < 2000, "{} rows before the error", good);
+    assert!(read[good].is_err());
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::{TempDb, int, rows, text};
+
+#[test]
+fn views_run_their_pick_every_time() {
+    let db = TempDb::new("views");
+    let mut conn = db.open();
+    conn.execute("make table people( name : string, age : int, team : string )")
+        .unwrap();
+    conn.execute("people.add( ( ann, 30, red ), ( bob, 15, blue ) )")
+        .unwrap();
+    conn.execute("make view adults as people.pick( name, team ) where ( age >= 18 )")
+        .unwrap();
+    conn.execute("people.add( ( cy, 40, red ) )").unwrap();
+    drop(conn);
+
+    let mut conn = db.open();
+    let found = rows(&mut conn, "adults.pick( name ) order by name desc");
+    assert_eq!(found, [[text("cy")], [text("ann")]]);
+    let found = rows(&mut conn, "adults.pick( * ) where ( name == cy )");
+    assert_eq!(found, [[text("cy"), text("red")]]);
+
+    conn.execute("make view teams as adults.pick( team, count(*) as members ) group by team")
+        .unwrap();
+    assert_eq!(
+        rows(&mut conn, "teams.pick( team, members )"),
+        [[text("red"), int(2)]]
+    );
+    let found = rows(
+        &mut conn,
+        "people.join( teams on people.team == teams.team ).pick( people.name, teams.members ) order by people.name",
+    );
+    assert_eq!(found, [[text("ann"), int(2)], [text("cy"), int(2)]]);
+}
+
+#[test]
+fn views_and_tables_keep_apart() {
+    let db = TempDb::new("view-names");
+    let mut conn = db.open();
+    conn.execute("make table t( a : int )").unwrap();
+    conn.execute("make view v as t.pick( a )").unwrap();
+
+    assert!(conn.execute("make view t as t.pick( a )").is_err());
+    assert!(conn.execute("make view v as t.pick( a )").is_err());
+    assert!(conn.execute("make table v( a : int )").is_err());
+    assert!(conn.execute("make view w as t.pick( nope )").is_err());
+    assert!(conn.execute("v.add( 1 )").is_err());
+    assert!(rows(&mut conn, "v.pick( a )").is_empty());
+}